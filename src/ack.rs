@@ -0,0 +1,112 @@
+use flume::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::{fmt, io, path::PathBuf, sync::Arc, time::Duration};
+
+pub(crate) type AckSender = Sender<Result<WriteReport, WriteError>>;
+
+/// [`WriteLocal::write_with_ack`](crate::WriteLocal::write_with_ack)返回的句柄，用于获取数据的写入结果
+#[must_use = "dropping WriteAck discards the write result"]
+pub struct WriteAck {
+    rx: Receiver<Result<WriteReport, WriteError>>,
+}
+
+impl WriteAck {
+    pub(crate) fn new() -> (AckSender, Self) {
+        let (tx, rx) = flume::bounded(1);
+        (tx, Self { rx })
+    }
+
+    /// 阻塞等待，直到数据被写入本地文件或写入失败
+    pub fn wait(self) -> Result<WriteReport, WriteError> {
+        self.rx.recv().unwrap_or(Err(WriteError::Closed))
+    }
+
+    /// 异步等待，直到数据被写入本地文件或写入失败
+    #[cfg(feature = "async")]
+    pub async fn wait_async(self) -> Result<WriteReport, WriteError> {
+        self.rx
+            .recv_async()
+            .await
+            .unwrap_or(Err(WriteError::Closed))
+    }
+
+    /// 最多等待`timeout`，超时返回`None`
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Result<WriteReport, WriteError>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(res) => Some(res),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(Err(WriteError::Closed)),
+        }
+    }
+
+    /// 不阻塞地检查写入结果，尚未写入时返回`None`
+    pub fn try_wait(&self) -> Option<Result<WriteReport, WriteError>> {
+        match self.rx.try_recv() {
+            Ok(res) => Some(res),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(WriteError::Closed)),
+        }
+    }
+}
+
+/// 数据成功写入本地文件后的报告
+#[derive(Debug, Clone)]
+pub struct WriteReport {
+    /// 写入的文件
+    pub path: PathBuf,
+    /// 本次写入文件的字节数，包括与之合并的其它批次的数据
+    pub bytes: usize,
+    /// 本次写入共合并了多少批次的数据(包括自身)
+    pub batches: usize,
+}
+
+impl WriteReport {
+    /// 是否与其它批次的数据合并后一起写入
+    pub fn merged(&self) -> bool {
+        self.batches > 1
+    }
+}
+
+/// 数据写入本地文件失败的原因
+#[derive(Debug, Clone)]
+pub enum WriteError {
+    /// 打开或写入文件时出错
+    Io {
+        path: PathBuf,
+        source: Arc<io::Error>,
+    },
+    /// 后台写线程已关闭，数据未被写入
+    Closed,
+    /// channel已满，数据按[`OverflowPolicy`](crate::OverflowPolicy)被丢弃
+    Dropped,
+    /// channel已满(见[`OverflowPolicy::Spill`](crate::OverflowPolicy::Spill))或者最终未能写入目标文件
+    /// (见[`Builder::spill_dir`](crate::Builder::spill_dir))，数据被写入了溢出文件`spill_file`。
+    /// 合并后的随机写入每一段各写入一个溢出文件，`spill_file`为其中第一个
+    Spilled { path: PathBuf, spill_file: PathBuf },
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Io { path, source } => {
+                write!(f, "failed to write {:?}: {source}", path.as_os_str())
+            }
+            WriteError::Closed => f.write_str("write local thread closed"),
+            WriteError::Dropped => f.write_str("write local channel full, data dropped"),
+            WriteError::Spilled { path, spill_file } => write!(
+                f,
+                "data for {:?} spilled to {:?}",
+                path.as_os_str(),
+                spill_file.as_os_str()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriteError::Io { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
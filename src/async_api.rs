@@ -0,0 +1,155 @@
+use crate::{
+    Command, FlushSummary, Inner, OverflowPolicy, Worker, WriteAck, WriteData, WriteError,
+    WriteLocal,
+};
+use flume::SendError;
+use std::{
+    future::{poll_fn, Future},
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 供async代码调用的版本，channel已满或等待写入完成时只挂起当前任务，不会阻塞线程。
+/// 后台写线程仍是一个独立的线程
+impl WriteLocal {
+    /// 同[`WriteLocal::write`]的async版本
+    ///
+    /// channel已满时按[`OverflowPolicy`]处理，需要等待channel有空位时挂起当前任务。
+    /// 使用[`OverflowPolicy::Spill`]时，溢出的数据仍在当前线程上写入溢出目录，期间会阻塞
+    pub async fn write_async(&self, dest_file: PathBuf, data: WriteData) {
+        self.submit_async(Command::Write(dest_file, data, None))
+            .await;
+    }
+
+    /// 同[`WriteLocal::write_with_ack`]的async版本，通过[`WriteAck::wait_async`]等待写入结果
+    pub async fn write_with_ack_async(&self, dest_file: PathBuf, data: WriteData) -> WriteAck {
+        let (ack_tx, ack) = WriteAck::new();
+        self.submit_async(Command::Write(dest_file, data, Some(ack_tx)))
+            .await;
+        ack
+    }
+
+    async fn submit_async(&self, cmd: Command) {
+        let inner = &self.inner;
+        let worker = inner.route(&cmd);
+        let Some(cmd) = self.try_submit(worker, cmd) else {
+            return;
+        };
+        if let Err(SendError(Command::Write(.., Some(ack)))) = inner.send_async(worker, cmd).await {
+            let _ = ack.send(Err(WriteError::Closed));
+        }
+    }
+
+    /// 同[`WriteLocal::flush`]的async版本
+    pub async fn flush_async(&self) -> Option<FlushSummary> {
+        self.flush_inner_async(None).await
+    }
+
+    /// 同[`WriteLocal::flush_path`]的async版本
+    pub async fn flush_path_async(&self, dest_file: &Path) -> Option<FlushSummary> {
+        self.flush_inner_async(Some(dest_file.to_path_buf())).await
+    }
+
+    async fn flush_inner_async(&self, path: Option<PathBuf>) -> Option<FlushSummary> {
+        let workers = match &path {
+            Some(path) => std::slice::from_ref(self.inner.worker(path)),
+            None => &self.inner.workers[..],
+        };
+        let mut pending = Vec::new();
+        let mut closed = false;
+        for worker in workers {
+            let (done, rx) = flume::bounded(1);
+            let path = path.clone();
+            match self
+                .inner
+                .send_async(worker, Command::Flush { path, done })
+                .await
+            {
+                Ok(()) => pending.push(rx),
+                Err(_) => closed = true,
+            }
+        }
+        // 有一个线程已关闭时返回`None`，但仍等其它线程写完
+        let mut summary = Some(FlushSummary::default());
+        for rx in pending {
+            match rx.recv_async().await {
+                Ok(worker_summary) => summary = summary.map(|s| s.merge(worker_summary)),
+                Err(_) => closed = true,
+            }
+        }
+        summary.filter(|_| !closed)
+    }
+
+    /// 同[`WriteLocal::shutdown`]的async版本
+    ///
+    /// 后台写线程发回最后一轮写入的汇总结果后就会退出，因此不再join它，以免阻塞当前线程
+    pub async fn shutdown_async(&self) -> Option<FlushSummary> {
+        let inner = &self.inner;
+        let _handles = inner.take_handles()?;
+
+        let mut pending = Vec::new();
+        for worker in &inner.workers {
+            let (done, rx) = flume::bounded(1);
+            let _ = inner
+                .send_async(worker, Command::Shutdown(Some(done)))
+                .await;
+            pending.push(rx);
+        }
+        let mut summary = Some(FlushSummary::default());
+        for rx in pending {
+            match rx.recv_async().await {
+                Ok(worker_summary) => summary = summary.map(|s| s.merge(worker_summary)),
+                Err(_) => {
+                    tracing::error!("write local thread panicked");
+                    summary = None;
+                }
+            }
+        }
+        inner.closed();
+        summary
+    }
+}
+
+impl Inner {
+    /// [`Inner::send`]的async版本，channel已满时挂起等待
+    ///
+    /// 使用[`OverflowPolicy::DropOldest`]时，在锁内登记等待，之后由[`Worker::waiting`]
+    /// 阻止其它发送者取出channel中的消息，不必在挂起期间持有锁
+    async fn send_async(&self, worker: &Worker, cmd: Command) -> Result<(), SendError<Command>> {
+        if self.overflow != OverflowPolicy::DropOldest {
+            return worker.tx.send_async(cmd).await;
+        }
+        // 先于`send`声明，被取消时在登记的等待被撤销之后才减少计数
+        let mut waiting = None;
+        let mut send = pin!(worker.tx.send_async(cmd));
+        poll_fn(|cx| {
+            if waiting.is_some() {
+                return send.as_mut().poll(cx);
+            }
+            let _guard = self.order_guard(worker);
+            let poll = send.as_mut().poll(cx);
+            if poll.is_pending() {
+                waiting = Some(Waiting::new(&worker.waiting));
+            }
+            poll
+        })
+        .await
+    }
+}
+
+/// 在channel上挂起等待期间计入[`Worker::waiting`]
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::AcqRel);
+        Self(count)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
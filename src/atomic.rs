@@ -0,0 +1,65 @@
+use crate::{
+    payload::Payload,
+    sink::{write_all, Sink},
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// 同一进程内临时文件的序号，避免多个临时文件重名
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 覆盖写入时，是否保留原文件的属性
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Preserve {
+    pub(crate) permissions: bool,
+    pub(crate) owner: bool,
+}
+
+/// 原子地覆盖写入`path`：先写入同目录下的临时文件并fsync，再改名覆盖`path`，最后fsync所在目录。
+/// 其它进程读到的要么是旧文件，要么是完整的新文件，中途崩溃也不会损坏原文件
+pub(crate) fn write_atomic(
+    sink: &dyn Sink,
+    path: &Path,
+    data: &Payload,
+    preserve: Preserve,
+) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let tmp = tmp_path(dir, path);
+
+    let res = write_tmp(sink, &tmp, path, data, preserve).and_then(|_| sink.rename(&tmp, path));
+    if res.is_err() {
+        let _ = sink.remove(&tmp);
+    }
+    res?;
+
+    sink.sync_dir(dir)
+}
+
+fn tmp_path(dir: &Path, path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!(".{name}.{}.{seq}.tmp", std::process::id()))
+}
+
+fn write_tmp(
+    sink: &dyn Sink,
+    tmp: &Path,
+    path: &Path,
+    data: &Payload,
+    preserve: Preserve,
+) -> io::Result<()> {
+    let mut file = sink.create(tmp)?;
+    write_all(file.as_mut(), data, &mut 0)?;
+
+    if preserve.permissions || preserve.owner {
+        sink.copy_attributes(path, tmp, preserve.permissions, preserve.owner)?;
+    }
+
+    file.sync(false)
+}
@@ -0,0 +1,270 @@
+use crate::{
+    retry::DeadLetterHandler,
+    sink::{SharedSink, Sink},
+    stats::Counters,
+    writer::{write_to_local, Config},
+    Command, DeadLetter, Durability, OverflowPolicy, RetryPolicy, Worker, WriteLocal,
+};
+use std::{io, path::PathBuf, sync::Arc, time::Duration};
+
+/// 自定义[`WriteLocal`]的配置
+///
+/// ```
+/// # use std::time::Duration;
+/// # use write_local::WriteLocal;
+/// let local_writer = WriteLocal::builder()
+///     .channel_capacity(10000)
+///     .batch_window(Duration::from_millis(20))
+///     .max_batch_bytes(4 * 1024 * 1024)
+///     .max_file_bytes(1024 * 1024)
+///     .thread_name("log-writer")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    capacity: Option<usize>,
+    workers: usize,
+    thread_name: String,
+    stack_size: Option<usize>,
+    overflow: OverflowPolicy,
+    config: Config,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            capacity: Some(1000),
+            workers: 1,
+            thread_name: "write_local".to_string(),
+            stack_size: None,
+            overflow: OverflowPolicy::default(),
+            config: Config::default(),
+        }
+    }
+}
+
+impl Builder {
+    /// channel最多能缓存多少条消息，满了之后[`WriteLocal::write`]会阻塞，默认1000
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// 使用不限容量的channel，[`WriteLocal::write`]永不阻塞
+    pub fn unbounded(mut self) -> Self {
+        self.capacity = None;
+        self
+    }
+
+    /// channel已满时如何处理新数据，默认[`OverflowPolicy::Block`]，使用不限容量的channel时不起作用
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    /// 后台写线程收到第一条消息后，最多再等待多久以收集更多要合并的数据，默认100ms
+    pub fn batch_window(mut self, window: Duration) -> Self {
+        self.config.batch_window = window;
+        self
+    }
+
+    /// 等待期间收集到的数据总共达到`bytes`字节时，不再等待而是立即写入，默认不限制
+    pub fn max_batch_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_batch_bytes = Some(bytes);
+        self
+    }
+
+    /// 等待期间某个文件待写入的数据达到`bytes`字节时，不再等待而是立即写入，默认不限制
+    pub fn max_file_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_file_bytes = Some(bytes);
+        self
+    }
+
+    /// 等待期间收集到`count`条要写入的数据时，不再等待而是立即写入，默认不限制
+    pub fn max_batch_count(mut self, count: usize) -> Self {
+        self.config.max_batch_count = Some(count);
+        self
+    }
+
+    /// 后台写线程中所有尚未写入的数据(包括写入失败等待重试的数据)最多占用多少字节，默认不限制。
+    /// 达到上限时立即写入，写入后仍超出上限时，从数据最多的文件开始放弃写入失败的数据，
+    /// 放弃的数据按[`Builder::spill_dir`]和[`Builder::dead_letter`]处理
+    pub fn max_pending_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_pending_bytes = Some(bytes);
+        self
+    }
+
+    /// 没有待写数据的文件闲置多久后，后台写线程不再为其保留缓冲区，默认60秒
+    pub fn idle_path_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_path_timeout = timeout;
+        self
+    }
+
+    /// 最多缓存多少个追加写入的文件句柄，超出时关闭最久未使用的句柄，默认64。
+    /// 为0时不缓存，每次写入都重新打开文件
+    ///
+    /// 多个后台写线程时由各个线程平分(向上取整)。写得太慢被隔离到慢速通道的文件由单独的线程写入，
+    /// 每个慢速通道另有同样多的句柄缓存，因此最多同时打开约两倍于此的文件
+    pub fn max_open_files(mut self, max: usize) -> Self {
+        self.config.max_open_files = max;
+        self
+    }
+
+    /// 缓存的文件句柄闲置多久后关闭，默认30秒
+    pub fn idle_file_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_file_timeout = timeout;
+        self
+    }
+
+    /// 写入文件后要确保持久化到什么程度，默认[`Durability::None`]。
+    /// 可通过[`WriteLocal::set_durability`]为单个文件设置
+    ///
+    /// 原子覆盖写入(见[`Builder::atomic_override`])总是会fsync文件及其所在目录，不受此设置影响
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.config.durability = durability;
+        self
+    }
+
+    /// 写入失败时的重试策略，默认见[`RetryPolicy::default`]
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry = policy;
+        self
+    }
+
+    /// 重试次数用完、错误不可重试或者退出时仍未能写入的数据，交给`f`处理，默认只记录日志后丢弃。
+    /// 设置了[`Builder::spill_dir`]时，只有写入溢出目录也失败的数据才会交给`f`
+    ///
+    /// `f`在后台写线程中调用，应尽快返回
+    pub fn dead_letter(mut self, f: impl Fn(DeadLetter) + Send + Sync + 'static) -> Self {
+        self.config.dead_letter = Some(DeadLetterHandler(Arc::new(f)));
+        self
+    }
+
+    /// 重试次数用完、错误不可重试或者退出时仍未能写入的数据，写入该溢出目录，默认不设置。
+    /// 之后可通过[`WriteLocal::replay_spill`]重新写入目标文件
+    ///
+    /// 每份数据对应一个`.data`文件和一个记录原始路径、写入方式、溢出时间和失败原因的`.meta`文件
+    pub fn spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.spill_dir = Some(dir.into());
+        self
+    }
+
+    /// 覆盖写入时，是否先写入同目录下的临时文件并fsync，再改名覆盖目标文件并fsync目录，默认开启。
+    /// 开启后其它进程不会读到写了一半的文件，中途崩溃也不会损坏原文件
+    ///
+    /// 关闭时直接截断目标文件后写入
+    pub fn atomic_override(mut self, enable: bool) -> Self {
+        self.config.atomic_override = enable;
+        self
+    }
+
+    /// 原子覆盖写入时，是否保留原文件的权限，默认开启
+    pub fn preserve_permissions(mut self, enable: bool) -> Self {
+        self.config.preserve.permissions = enable;
+        self
+    }
+
+    /// 原子覆盖写入时，是否保留原文件的属主和属组，默认关闭。通常需要root权限，无权修改时保留当前用户
+    pub fn preserve_owner(mut self, enable: bool) -> Self {
+        self.config.preserve.owner = enable;
+        self
+    }
+
+    /// 写入数据的存储后端，默认为[`FsSink`](crate::FsSink)，写入本地文件系统
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.config.sink = SharedSink(Arc::new(sink));
+        self
+    }
+
+    #[cfg(feature = "test-util")]
+    pub(crate) fn clock(mut self, clock: crate::clock::Clock) -> Self {
+        self.config.clock = clock;
+        self
+    }
+
+    /// 写入单个文件的超时时间，默认不限
+    ///
+    /// 打开文件超时，或者一次写入(包括打开、写入和持久化)的耗时超过该值时，该文件被隔离到慢速通道：
+    /// 尚未写入的数据和之后收到的数据都交给单独的线程写入，不再拖慢其它文件。
+    /// 被隔离的文件的数据仍然按顺序写入，flush和shutdown也会等待慢速通道写完。
+    /// 已经开始的写入无法中断，只有打开文件可以在超时后立即放弃等待
+    pub fn slow_write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.slow_write_timeout = Some(timeout);
+        self
+    }
+
+    /// 后台写线程的数量，默认1
+    ///
+    /// 多个线程时按文件路径的哈希值分配数据，同一个文件的数据总是由同一个线程按顺序写入，
+    /// 不同的文件可以并行写入，一个文件写得慢不会拖慢其它线程负责的文件。
+    /// 每个线程有各自的channel，容量均为[`Builder::channel_capacity`]；
+    /// [`Builder::max_pending_bytes`]和[`Builder::max_open_files`]则由各个线程平分
+    pub fn worker_threads(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// 后台写线程的名称，默认`write_local`，多个线程时依次为`write_local-0`、`write_local-1`……
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// 后台写线程的栈大小，默认使用标准库的默认值
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// 启动后台写线程
+    pub fn build(self) -> io::Result<WriteLocal> {
+        let spill_dirs = self.spill_dirs();
+        let counters = Arc::new(Counters::new(self.workers));
+        let mut config = self.config;
+        config.max_pending_bytes = config
+            .max_pending_bytes
+            .map(|max| max.div_ceil(self.workers));
+        config.max_open_files = config.max_open_files.div_ceil(self.workers);
+
+        let mut workers = Vec::with_capacity(self.workers);
+        for i in 0..self.workers {
+            let (tx, rx) = match self.capacity {
+                Some(capacity) => flume::bounded::<Command>(capacity),
+                None => flume::unbounded::<Command>(),
+            };
+            let oldest = (self.overflow == OverflowPolicy::DropOldest).then(|| rx.clone());
+
+            let name = match self.workers {
+                1 => self.thread_name.clone(),
+                _ => format!("{}-{i}", self.thread_name),
+            };
+            let mut thread = std::thread::Builder::new().name(name);
+            if let Some(size) = self.stack_size {
+                thread = thread.stack_size(size);
+            }
+            let config = config.clone();
+            let worker_counters = counters.clone();
+            // 前面的线程已经启动，出错时随着workers被drop，它们的channel关闭后自行退出
+            let handle = thread.spawn(move || write_to_local(rx, config, worker_counters, i))?;
+            workers.push(Worker::new(tx, handle, oldest));
+        }
+
+        Ok(WriteLocal::new(
+            workers,
+            counters,
+            self.overflow,
+            spill_dirs,
+        ))
+    }
+
+    fn spill_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<_> = self.config.spill_dir.iter().cloned().collect();
+        if let OverflowPolicy::Spill(dir) = &self.overflow {
+            if !dirs.contains(dir) {
+                dirs.push(dir.clone());
+            }
+        }
+        dirs
+    }
+}
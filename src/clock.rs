@@ -0,0 +1,77 @@
+use std::time::{Instant, SystemTime};
+#[cfg(feature = "test-util")]
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+/// 后台写线程使用的时钟，启用`test-util` feature时可以换成手动推进的时钟
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Clock {
+    #[cfg(feature = "test-util")]
+    manual: Option<Arc<Mutex<Manual>>>,
+}
+
+/// 手动推进的时钟当前的时间
+#[cfg(feature = "test-util")]
+#[derive(Debug)]
+struct Manual {
+    now: Instant,
+    wall: SystemTime,
+}
+
+impl Clock {
+    pub(crate) fn now(&self) -> Instant {
+        #[cfg(feature = "test-util")]
+        if let Some(manual) = &self.manual {
+            return manual.lock().unwrap_or_else(PoisonError::into_inner).now;
+        }
+        Instant::now()
+    }
+
+    /// 当前的日期时间，用于按时间轮转文件
+    pub(crate) fn wall(&self) -> SystemTime {
+        #[cfg(feature = "test-util")]
+        if let Some(manual) = &self.manual {
+            return manual.lock().unwrap_or_else(PoisonError::into_inner).wall;
+        }
+        SystemTime::now()
+    }
+
+    /// 是否是手动推进的时钟
+    pub(crate) fn is_manual(&self) -> bool {
+        #[cfg(feature = "test-util")]
+        return self.manual.is_some();
+        #[cfg(not(feature = "test-util"))]
+        false
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Clock {
+    /// 停在当前时间，只有调用[`Clock::advance`]时才前进的时钟
+    pub(crate) fn manual() -> Self {
+        let manual = Manual {
+            now: Instant::now(),
+            wall: SystemTime::now(),
+        };
+        Self {
+            manual: Some(Arc::new(Mutex::new(manual))),
+        }
+    }
+
+    pub(crate) fn advance(&self, duration: Duration) {
+        if let Some(manual) = &self.manual {
+            let mut manual = manual.lock().unwrap_or_else(PoisonError::into_inner);
+            manual.now += duration;
+            manual.wall += duration;
+        }
+    }
+
+    /// 将日期时间设置为`wall`，不影响[`Clock::now`]
+    pub(crate) fn set_wall(&self, wall: SystemTime) {
+        if let Some(manual) = &self.manual {
+            manual.lock().unwrap_or_else(PoisonError::into_inner).wall = wall;
+        }
+    }
+}
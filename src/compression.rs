@@ -0,0 +1,79 @@
+use crate::payload::Payload;
+use std::io;
+
+/// 写入前对数据的压缩方式，见[`WriteLocal::set_compression`](crate::WriteLocal::set_compression)
+///
+/// 追加写入时每轮写入的数据压缩为一个独立的gzip member或zstd frame，依次追加到文件末尾，
+/// 整个文件仍然可以用`gzip -d`或`zstd -d`直接解压；覆盖写入时整个文件内容压缩后写入
+///
+/// 放弃写入时，交给dead letter回调或写入溢出目录的也是压缩后的数据
+///
+/// 各种压缩方式需要启用对应的cargo feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Compression {
+    /// 不压缩
+    #[default]
+    None,
+    /// gzip压缩，参数为压缩级别0-9
+    #[cfg(feature = "gzip")]
+    Gzip(u32),
+    /// zstd压缩，参数为压缩级别1-22，0表示zstd的默认级别
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+impl Compression {
+    pub(crate) fn is_none(self) -> bool {
+        self == Compression::None
+    }
+
+    /// 记录在溢出文件中的名称
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(_) => "gzip",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(_) => "zstd",
+        }
+    }
+
+    /// 由[`Compression::name`]还原，压缩级别不影响解压，取默认值。未启用对应的feature时返回`None`
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Compression::None),
+            #[cfg(feature = "gzip")]
+            "gzip" => Some(Compression::Gzip(6)),
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Compression::Zstd(0)),
+            _ => None,
+        }
+    }
+
+    /// 将`data`的各段数据压缩为一个完整的gzip member或zstd frame
+    pub(crate) fn compress(self, data: &Payload) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip(level) => {
+                use std::io::Write as _;
+                let level = flate2::Compression::new(level.min(9));
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                for chunk in data.chunks() {
+                    encoder.write_all(chunk)?;
+                }
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => {
+                use std::io::Write as _;
+                let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), level)?;
+                for chunk in data.chunks() {
+                    encoder.write_all(chunk)?;
+                }
+                encoder.finish()
+            }
+        }
+    }
+}
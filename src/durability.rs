@@ -0,0 +1,71 @@
+use crate::sink::{Sink, SinkFile};
+use std::{io, path::Path};
+
+/// 数据写入文件后，要确保持久化到什么程度
+///
+/// 同一轮写入中合并到同一个文件的所有批次的数据，只会同步一次
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability {
+    /// 写入后不做任何同步，数据可能还在操作系统的缓存中，断电时可能丢失
+    #[default]
+    None,
+    /// 写入后调用`flush`，只确保数据已经交给了操作系统
+    FlushOnly,
+    /// 写入后调用`fdatasync`，确保数据持久化，但不一定同步修改时间等元数据
+    Fdatasync,
+    /// 写入后调用`fsync`，确保数据和元数据都持久化
+    Fsync,
+    /// 同[`Durability::Fsync`]，并且fsync文件所在目录，确保新建的文件本身也持久化
+    FsyncDir,
+}
+
+impl Durability {
+    /// 对刚写入数据的`file`执行同步
+    pub(crate) fn sync(
+        self,
+        file: &mut dyn SinkFile,
+        path: &Path,
+        sink: &dyn Sink,
+    ) -> io::Result<()> {
+        match self {
+            Durability::None => Ok(()),
+            Durability::FlushOnly => file.flush(),
+            Durability::Fdatasync => file.sync(true),
+            Durability::Fsync => file.sync(false),
+            Durability::FsyncDir => {
+                file.sync(false)?;
+                sync_parent(path, sink)
+            }
+        }
+    }
+
+    /// 交给[`Sink::append_batch`]在写完后执行的同步，见[`BatchAppend::sync`](crate::BatchAppend::sync)
+    pub(crate) fn file_sync(self) -> Option<bool> {
+        match self {
+            Durability::None | Durability::FlushOnly => None,
+            Durability::Fdatasync => Some(true),
+            Durability::Fsync | Durability::FsyncDir => Some(false),
+        }
+    }
+
+    /// [`Sink::append_batch`]写完并同步文件后，还需要执行的同步
+    pub(crate) fn after_file_sync(
+        self,
+        file: &mut dyn SinkFile,
+        path: &Path,
+        sink: &dyn Sink,
+    ) -> io::Result<()> {
+        match self {
+            Durability::FlushOnly => file.flush(),
+            Durability::FsyncDir => sync_parent(path, sink),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn sync_parent(path: &Path, sink: &dyn Sink) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sink.sync_dir(dir),
+        _ => sink.sync_dir(Path::new(".")),
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    clock::Clock,
+    sink::{Sink, SinkFile},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// 后台写线程缓存的追加写或随机写文件句柄，避免每次写入都重新打开文件
+///
+/// 最多缓存`max_open`个句柄，超出时关闭最久未使用的句柄，闲置超过`idle_timeout`的句柄也会被关闭。
+/// 每次取用句柄前都会检查路径对应的文件是否还是打开时的那个文件，
+/// 文件被删除或被轮转(改名后新建同名文件)时重新打开
+pub(crate) struct HandleCache {
+    sink: Arc<dyn Sink>,
+    clock: Clock,
+    max_open: usize,
+    idle_timeout: Duration,
+    files: HashMap<PathBuf, Handle>,
+}
+
+/// 打开文件的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpenMode {
+    /// 通过[`Sink::open`]追加写入
+    Append,
+    /// 通过[`Sink::open_positional`]随机写入
+    Positional,
+}
+
+/// 缓存的或者被取出的文件句柄
+pub(crate) struct Handle {
+    pub(crate) file: Box<dyn SinkFile>,
+    mode: OpenMode,
+    id: Option<(u64, u64)>,
+    last_used: Instant,
+}
+
+impl HandleCache {
+    pub(crate) fn new(
+        sink: Arc<dyn Sink>,
+        clock: Clock,
+        max_open: usize,
+        idle_timeout: Duration,
+    ) -> Self {
+        Self {
+            sink,
+            clock,
+            max_open,
+            idle_timeout,
+            files: HashMap::new(),
+        }
+    }
+
+    /// 以`mode`方式打开`path`，不存在时创建。`max_open`为0时不缓存句柄，每次都重新打开
+    ///
+    /// 需要打开文件时最多等待`open_timeout`，超时返回的错误可以通过[`is_open_timeout`]识别
+    pub(crate) fn with_file<T>(
+        &mut self,
+        path: &Path,
+        mode: OpenMode,
+        open_timeout: Option<Duration>,
+        f: impl FnOnce(&mut dyn SinkFile) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut handle = self.checkout(path, mode, open_timeout)?;
+        let res = f(handle.file.as_mut());
+        // 写入出错的句柄可能已经不可用，下次重新打开
+        if res.is_ok() {
+            self.checkin(path, handle);
+        }
+        res
+    }
+
+    /// 取出`path`以`mode`方式打开的句柄，没有可用的缓存句柄时打开文件。
+    /// 用完后通过[`HandleCache::checkin`]放回，出错的句柄直接丢弃即可
+    pub(crate) fn checkout(
+        &mut self,
+        path: &Path,
+        mode: OpenMode,
+        open_timeout: Option<Duration>,
+    ) -> io::Result<Handle> {
+        if let Some(cached) = self.take_cached(path, mode) {
+            return Ok(cached);
+        }
+        let file = self.open(path, mode, open_timeout)?;
+        Ok(self.handle(file, mode))
+    }
+
+    /// 同[`HandleCache::checkout`]，以追加方式取出多个文件的句柄，
+    /// 没有可用缓存句柄的文件通过[`Sink::open_batch`]一起打开
+    pub(crate) fn checkout_batch(&mut self, paths: &[&Path]) -> Vec<io::Result<Handle>> {
+        let mut handles: Vec<_> = paths
+            .iter()
+            .map(|path| self.take_cached(path, OpenMode::Append).map(Ok))
+            .collect();
+        let missing: Vec<_> = paths
+            .iter()
+            .zip(&handles)
+            .filter(|(_, handle)| handle.is_none())
+            .map(|(path, _)| *path)
+            .collect();
+        if !missing.is_empty() {
+            let mut opened = self.sink.open_batch(&missing).into_iter();
+            for handle in handles.iter_mut().filter(|handle| handle.is_none()) {
+                let file = opened.next().unwrap_or_else(|| {
+                    Err(io::Error::other("Sink::open_batch returned too few files"))
+                });
+                *handle = Some(file.map(|file| self.handle(file, OpenMode::Append)));
+            }
+        }
+        handles.into_iter().flatten().collect()
+    }
+
+    /// 取出`path`以`mode`方式打开、且仍指向原来文件的缓存句柄
+    fn take_cached(&mut self, path: &Path, mode: OpenMode) -> Option<Handle> {
+        match self.files.remove(path) {
+            Some(cached) if cached.mode != mode => None,
+            Some(cached)
+                if cached.id.is_some()
+                    && cached.id == self.sink.metadata(path).ok().and_then(|m| m.id) =>
+            {
+                Some(cached)
+            }
+            Some(_) => {
+                debug!("{:?} was removed or rotated, reopen it", path.as_os_str());
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn handle(&self, file: Box<dyn SinkFile>, mode: OpenMode) -> Handle {
+        let id = file.metadata().ok().and_then(|meta| meta.id);
+        Handle {
+            file,
+            mode,
+            id,
+            last_used: self.clock.now(),
+        }
+    }
+
+    /// 放回取出的句柄，`max_open`为0时不缓存句柄，直接关闭
+    pub(crate) fn checkin(&mut self, path: &Path, mut handle: Handle) {
+        if self.max_open == 0 {
+            return;
+        }
+        if self.files.len() >= self.max_open {
+            self.close_lru();
+        }
+        handle.last_used = self.clock.now();
+        self.files.insert(path.to_path_buf(), handle);
+    }
+
+    /// 在单独的线程中打开文件，最多等待`timeout`。超时后该线程仍会继续，打开的文件随即被关闭
+    fn open(
+        &self,
+        path: &Path,
+        mode: OpenMode,
+        timeout: Option<Duration>,
+    ) -> io::Result<Box<dyn SinkFile>> {
+        let Some(timeout) = timeout else {
+            return mode.open(self.sink.as_ref(), path);
+        };
+        let (tx, rx) = flume::bounded(1);
+        let sink = self.sink.clone();
+        let owned = path.to_path_buf();
+        std::thread::Builder::new()
+            .name("write_local-open".to_string())
+            .spawn(move || {
+                let _ = tx.send(mode.open(sink.as_ref(), &owned));
+            })?;
+        rx.recv_timeout(timeout).unwrap_or_else(|_| {
+            let e = OpenTimedOut {
+                path: path.to_path_buf(),
+                timeout,
+            };
+            Err(io::Error::new(io::ErrorKind::TimedOut, e))
+        })
+    }
+
+    /// 关闭`path`的缓存句柄
+    pub(crate) fn close(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// 最早的句柄闲置超时的时间点，没有缓存的句柄时返回`None`
+    pub(crate) fn idle_deadline(&self) -> Option<Instant> {
+        self.files
+            .values()
+            .map(|cached| cached.last_used + self.idle_timeout)
+            .min()
+    }
+
+    /// 关闭所有闲置超时的句柄
+    pub(crate) fn close_idle(&mut self) {
+        let now = self.clock.now();
+        let idle_timeout = self.idle_timeout;
+        self.files.retain(|path, cached| {
+            let keep = now.duration_since(cached.last_used) < idle_timeout;
+            if !keep {
+                debug!("close idle file {:?}", path.as_os_str());
+            }
+            keep
+        });
+    }
+
+    fn close_lru(&mut self) {
+        let lru = self
+            .files
+            .iter()
+            .min_by_key(|(_, cached)| cached.last_used)
+            .map(|(path, _)| path.clone());
+        if let Some(path) = lru {
+            self.files.remove(&path);
+        }
+    }
+}
+
+impl OpenMode {
+    fn open(self, sink: &dyn Sink, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        match self {
+            OpenMode::Append => sink.open(path),
+            OpenMode::Positional => sink.open_positional(path),
+        }
+    }
+}
+
+/// 打开文件等待超时，与存储本身返回的[`io::ErrorKind::TimedOut`](例如NFS的ETIMEDOUT)区分开
+#[derive(Debug)]
+struct OpenTimedOut {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl fmt::Display for OpenTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "open {:?} timed out after {:?}",
+            self.path.as_os_str(),
+            self.timeout
+        )
+    }
+}
+
+impl Error for OpenTimedOut {}
+
+/// `e`是否是[`HandleCache::checkout`]等待打开文件超时返回的错误
+pub(crate) fn is_open_timeout(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<OpenTimedOut>())
+}
@@ -1,148 +1,624 @@
-use flume::{bounded, Receiver, Sender};
-use fs_err::{write, OpenOptions};
-use std::{collections::HashMap, io::Write as _, path::PathBuf};
-use tracing::{error, info, warn};
+mod ack;
+#[cfg(feature = "async")]
+mod async_api;
+mod atomic;
+mod builder;
+mod clock;
+mod compression;
+mod durability;
+mod handles;
+mod merged;
+mod overflow;
+mod payload;
+mod retry;
+mod rotation;
+mod sink;
+mod slow_lane;
+mod spill;
+mod stats;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod writer;
+
+pub use ack::{WriteAck, WriteError, WriteReport};
+pub use builder::Builder;
+pub use compression::Compression;
+pub use durability::Durability;
+pub use overflow::{OverflowCounts, OverflowPolicy, SubmitError};
+pub use payload::Payload;
+pub use retry::{DeadLetter, RetryPolicy};
+pub use rotation::{RotationInterval, RotationPolicy};
+pub use sink::{BatchAppend, FsSink, MemorySink, Sink, SinkFile, SinkMetadata};
+pub use spill::ReplayReport;
+pub use stats::{FlushTrigger, LatencyStats, MemoryStats, PathStats, Stats, TriggerCounts};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use uring::UringSink;
+
+use ack::AckSender;
+use flume::{bounded, Receiver, SendError, SendTimeoutError, Sender, TrySendError};
+use stats::Counters;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use tracing::{error, warn};
+use writer::PathSetting;
 
 /// 集中(收集)所有要写入本地的数据，要写入同个文件的多批次数据尽可能地被合并，减少写入本地文件的次数
 ///
-/// 需注意，每次写入文件时，都会重新打开一次文件(写完自动关闭)
+/// 追加写入时打开的文件句柄会被缓存起来，闲置一段时间后才关闭，见[`Builder::max_open_files`]
+///
+/// 调用[`WriteLocal::shutdown`]或最后一个`WriteLocal`被drop时，会将所有尚未写入的数据写入本地，
+/// 并等待后台写线程退出
 ///
 /// ```
+/// # use std::{path::PathBuf, str::FromStr};
+/// # use write_local::{WriteData, WriteLocal};
 /// // 初始化
 /// let local_writer = WriteLocal::init();
 ///
 /// // 追加写入到文件
 /// let dest_file = PathBuf::from_str("/tmp/a.log").unwrap();
 /// let data = "helloworld".as_bytes().to_vec();
-/// local_writer.write(dest_file, WriteData::Append(data));
+/// local_writer.write(dest_file, WriteData::Append(data.into()));
+///
+/// // 退出前确保所有数据都已写入
+/// let summary = local_writer.shutdown();
 /// ```
 #[derive(Clone)]
 pub struct WriteLocal {
-    tx: Sender<(PathBuf, WriteData)>,
+    inner: Arc<Inner>,
+}
+
+/// 所有`WriteLocal`克隆共享的部分，最后一个`WriteLocal`被drop时，由它负责关闭后台写线程
+struct Inner {
+    /// 所有后台写线程，同一个文件的数据总是交给同一个线程，见[`Inner::worker`]
+    workers: Vec<Worker>,
+    counters: Arc<Counters>,
+    overflow: OverflowPolicy,
+    /// 所有的溢出目录，供[`WriteLocal::replay_spill`]使用
+    spill_dirs: Vec<PathBuf>,
+}
+
+/// 一个后台写线程
+struct Worker {
+    tx: Sender<Command>,
+    handle: Mutex<Option<JoinHandle<FlushSummary>>>,
+    /// 使用[`OverflowPolicy::DropOldest`]时，用于从channel中取出最早的消息，后台写线程退出后被清空。
+    /// 此时所有发送都持有该锁，见[`Inner::send_drop_oldest`]
+    oldest: Mutex<Option<Receiver<Command>>>,
+    /// 使用[`OverflowPolicy::DropOldest`]时，在channel上挂起等待空位的async发送者数量。
+    /// 大于0时不再取出channel中的消息，以免等待中的消息被排到取出又放回的消息之前
+    waiting: AtomicUsize,
+}
+
+impl Worker {
+    fn new(
+        tx: Sender<Command>,
+        handle: JoinHandle<FlushSummary>,
+        oldest: Option<Receiver<Command>>,
+    ) -> Self {
+        Self {
+            tx,
+            handle: Mutex::new(Some(handle)),
+            oldest: Mutex::new(oldest),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// 发送给后台写线程的消息
+pub(crate) enum Command {
+    /// 要写入的文件路径和数据，以及(可选的)等待写入结果的调用者
+    Write(PathBuf, WriteData, Option<AckSender>),
+    /// 写完所有已收到的数据后，将本轮写入的汇总结果发回，`path`不为空时只发回该文件的结果
+    Flush {
+        path: Option<PathBuf>,
+        done: Sender<FlushSummary>,
+    },
+    /// 修改某个文件的设置，对同一轮中尚未写入的数据也生效
+    Configure(PathBuf, PathSetting),
+    /// 被隔离的文件尚未写入的数据，由原来的后台写线程转交给慢速通道；
+    /// 重新写入已压缩的溢出数据时也以这种形式交给后台写线程
+    Transfer(PathBuf, Box<slow_lane::Transferred>),
+    /// 写完所有已收到的数据后退出，退出前将最后一轮写入的汇总结果发回
+    Shutdown(Option<Sender<FlushSummary>>),
+    /// 手动推进的时钟前进后，唤醒后台写线程处理到期的事件，处理完后回复
+    #[cfg(feature = "test-util")]
+    Tick(Sender<()>),
 }
 
 impl WriteLocal {
-    /// 初始化
+    /// 使用默认配置初始化，见[`Builder`]
     pub fn init() -> Self {
-        let (tx, rx) = bounded::<(PathBuf, WriteData)>(1000);
+        Self::builder()
+            .build()
+            .expect("failed to spawn write local thread")
+    }
 
-        std::thread::spawn(move || {
-            write_to_local(rx);
-        });
+    /// 自定义配置，见[`Builder`]
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
 
-        Self { tx }
+    fn new(
+        workers: Vec<Worker>,
+        counters: Arc<Counters>,
+        overflow: OverflowPolicy,
+        spill_dirs: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                workers,
+                counters,
+                overflow,
+                spill_dirs,
+            }),
+        }
     }
 
     /// 发送要写到本地文件的路径和数据
+    ///
+    /// channel已满时按[`OverflowPolicy`]处理，默认阻塞等待
     pub fn write(&self, dest_file: PathBuf, data: WriteData) {
-        let _ = self.tx.send((dest_file, data));
+        self.submit(Command::Write(dest_file, data, None));
     }
-}
-
-/// 待写入本地的(字节)数据是要追加的还是截断覆盖原有数据的
-pub enum WriteData {
-    Append(Vec<u8>),
-    Override(Vec<u8>),
-}
 
-impl WriteData {
-    /// 覆盖类型的数据，直接替换，追加类型的数据，直接追加在尾部
-    fn write(&mut self, data: Vec<u8>) {
-        match self {
-            WriteData::Append(local) => local.extend(data),
-            WriteData::Override(local) => *local = data,
+    /// 不阻塞地发送要写到本地文件的路径和数据，channel已满时将数据原样返回
+    pub fn try_write(&self, dest_file: PathBuf, data: WriteData) -> Result<(), SubmitError> {
+        let worker = self.inner.worker(&dest_file);
+        let _guard = self.inner.order_guard(worker);
+        match worker.tx.try_send(Command::Write(dest_file, data, None)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(Command::Write(f, d, _))) => Err(SubmitError::Full(f, d)),
+            Err(TrySendError::Disconnected(Command::Write(f, d, _))) => {
+                Err(SubmitError::Closed(f, d))
+            }
+            Err(_) => unreachable!(),
         }
     }
 
-    /// 数据已经写入本地之后，清空这些已写数据
-    fn clear(&mut self) {
-        match self {
-            WriteData::Append(local) => local.clear(),
-            WriteData::Override(local) => local.clear(),
+    /// 发送要写到本地文件的路径和数据，channel已满时最多等待`timeout`，超时后将数据原样返回
+    ///
+    /// 使用[`OverflowPolicy::DropOldest`]时，等待期间发送给同一后台写线程的其它消息也要等待
+    pub fn write_timeout(
+        &self,
+        dest_file: PathBuf,
+        data: WriteData,
+        timeout: Duration,
+    ) -> Result<(), SubmitError> {
+        let worker = self.inner.worker(&dest_file);
+        let _guard = self.inner.order_guard(worker);
+        match worker
+            .tx
+            .send_timeout(Command::Write(dest_file, data, None), timeout)
+        {
+            Ok(()) => Ok(()),
+            Err(SendTimeoutError::Timeout(Command::Write(f, d, _))) => {
+                Err(SubmitError::Timeout(f, d))
+            }
+            Err(SendTimeoutError::Disconnected(Command::Write(f, d, _))) => {
+                Err(SubmitError::Closed(f, d))
+            }
+            Err(_) => unreachable!(),
         }
     }
 
-    #[allow(dead_code)]
-    fn len(&self) -> usize {
-        match self {
-            WriteData::Append(local) => local.len(),
-            WriteData::Override(local) => local.len(),
+    /// 同[`WriteLocal::write`]，但返回一个句柄，通过它可以得知数据最终是否写入成功
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use write_local::{WriteData, WriteLocal};
+    /// let local_writer = WriteLocal::init();
+    /// let ack = local_writer.write_with_ack(
+    ///     PathBuf::from("/tmp/b.log"),
+    ///     WriteData::Append(b"helloworld".to_vec().into()),
+    /// );
+    /// match ack.wait() {
+    ///     Ok(report) => println!("{} bytes written", report.bytes),
+    ///     Err(e) => eprintln!("{e}"),
+    /// }
+    /// ```
+    pub fn write_with_ack(&self, dest_file: PathBuf, data: WriteData) -> WriteAck {
+        let (ack_tx, ack) = WriteAck::new();
+        self.submit(Command::Write(dest_file, data, Some(ack_tx)));
+        ack
+    }
+
+    /// 按[`OverflowPolicy`]发送消息，需要等待channel有空位时阻塞等待
+    fn submit(&self, cmd: Command) {
+        let worker = self.inner.route(&cmd);
+        if let Some(cmd) = self.try_submit(worker, cmd) {
+            if let Err(SendError(Command::Write(.., Some(ack)))) = self.inner.send(worker, cmd) {
+                let _ = ack.send(Err(WriteError::Closed));
+            }
         }
     }
 
-    fn is_empty(&self) -> bool {
-        match self {
-            WriteData::Append(local) => local.is_empty(),
-            WriteData::Override(local) => local.is_empty(),
+    /// 按[`OverflowPolicy`]不阻塞地发送消息，需要等待channel有空位时将消息返回。
+    /// 因溢出或后台写线程已关闭而未能交给后台写线程的数据，通知等待写入结果的调用者
+    fn try_submit(&self, worker: &Worker, cmd: Command) -> Option<Command> {
+        let inner = &self.inner;
+        let res = match &inner.overflow {
+            OverflowPolicy::Block => return Some(cmd),
+            OverflowPolicy::DropNewest => match worker.tx.try_send(cmd) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(cmd)) => {
+                    inner.counters.overflowed(&inner.overflow);
+                    warn!("write local channel full, drop newest data");
+                    Err((cmd, WriteError::Dropped))
+                }
+                Err(TrySendError::Disconnected(cmd)) => Err((cmd, WriteError::Closed)),
+            },
+            OverflowPolicy::DropOldest => match inner.send_drop_oldest(worker, cmd) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(cmd)) => return Some(cmd),
+                Err(TrySendError::Disconnected(cmd)) => Err((cmd, WriteError::Closed)),
+            },
+            OverflowPolicy::Spill(dir) => match worker.tx.try_send(cmd) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(Command::Write(f, d, ack))) => {
+                    let reason = io::Error::other("write local channel full");
+                    match spill::spill(dir, &f, &d, Compression::None, &reason) {
+                        Ok(spill_file) => {
+                            inner.counters.overflowed(&inner.overflow);
+                            warn!(
+                                "write local channel full, spill {:?} to {:?}",
+                                f, spill_file
+                            );
+                            let err = WriteError::Spilled {
+                                path: f.clone(),
+                                spill_file,
+                            };
+                            Err((Command::Write(f, d, ack), err))
+                        }
+                        // 溢出文件也写不了，只能等待channel有空位
+                        Err(e) => {
+                            error!("{e}");
+                            return Some(Command::Write(f, d, ack));
+                        }
+                    }
+                }
+                Err(TrySendError::Full(cmd) | TrySendError::Disconnected(cmd)) => {
+                    Err((cmd, WriteError::Closed))
+                }
+            },
+        };
+        if let Err((Command::Write(.., Some(ack)), err)) = res {
+            let _ = ack.send(Err(err));
         }
+        None
     }
-}
 
-fn write_to_local(rx: Receiver<(PathBuf, WriteData)>) {
-    let mut cached: HashMap<PathBuf, WriteData> = HashMap::with_capacity(10);
-    let mut tmp = Vec::with_capacity(10);
-    loop {
-        // 先用recv阻塞接收消息，然后通过rx.drain()一次性读取channel中的所有消息
-        match rx.recv() {
-            Ok(data) => tmp.push(data),
-            Err(e) => {
-                warn!("write local channel sender closed: {e}");
-                break;
-            }
+    /// 阻塞等待，直到在此之前发送的所有数据都已经写入本地文件，返回本轮写入的汇总结果
+    ///
+    /// 写入失败的数据会记录在汇总结果中，并保留下来稍后重试。后台写线程已关闭(包括panic)时返回`None`
+    pub fn flush(&self) -> Option<FlushSummary> {
+        self.flush_inner(None)
+    }
+
+    /// 阻塞等待，直到在此之前发送的所有数据都已经写入本地文件，只返回`dest_file`的写入结果
+    ///
+    /// 负责`dest_file`的后台写线程已关闭(包括panic)时返回`None`
+    pub fn flush_path(&self, dest_file: &Path) -> Option<FlushSummary> {
+        self.flush_inner(Some(dest_file.to_path_buf()))
+    }
+
+    /// `path`为空时通知所有后台写线程写入，并合并它们的汇总结果。
+    /// 有一个线程已关闭时返回`None`，但仍等其它线程写完
+    fn flush_inner(&self, path: Option<PathBuf>) -> Option<FlushSummary> {
+        let workers = match &path {
+            Some(path) => std::slice::from_ref(self.inner.worker(path)),
+            None => &self.inner.workers[..],
         };
+        let pending: Vec<_> = workers
+            .iter()
+            .map(|worker| {
+                let (done, rx) = bounded(1);
+                let path = path.clone();
+                self.inner
+                    .send(worker, Command::Flush { path, done })
+                    .ok()
+                    .map(|_| rx)
+            })
+            .collect();
+        let summaries: Vec<_> = pending.into_iter().map(|rx| rx?.recv().ok()).collect();
+        summaries
+            .into_iter()
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .reduce(FlushSummary::merge)
+    }
 
-        // 稍稍小睡一会会，等待更多数据的到来
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    /// 设置追加写入`dest_file`时的轮转策略，`None`表示不再轮转
+    ///
+    /// 轮转由后台写线程在两轮写入之间进行，不会与写入竞争
+    pub fn set_rotation(&self, dest_file: PathBuf, policy: Option<RotationPolicy>) {
+        let setting = PathSetting::Rotation(policy.map(Box::new));
+        self.inner.configure(dest_file, setting);
+    }
 
-        tmp.extend(rx.drain()); // flume的Receiver::drain()是不阻塞的，总是立即返回
+    /// 设置写入`dest_file`前对数据的压缩方式，见[`Compression`]
+    ///
+    /// 压缩在单独的线程中进行，不会推迟其它文件的写入
+    pub fn set_compression(&self, dest_file: PathBuf, compression: Compression) {
+        let setting = PathSetting::Compression(compression);
+        self.inner.configure(dest_file, setting);
+    }
 
-        // 合并要写的内容
-        for (f, d) in tmp.drain(..) {
-            // 接收到了空数据想要写入
-            if d.is_empty() {
-                warn!("recv empty data want write to {:?}, skip", f.as_os_str());
-                continue;
-            }
-            match d {
-                WriteData::Append(data) => cached
-                    .entry(f)
-                    .or_insert(WriteData::Append(Vec::new()))
-                    .write(data),
-                WriteData::Override(data) => {
-                    cached
-                        .entry(f)
-                        .or_insert(WriteData::Override(Vec::new()))
-                        .write(data);
+    /// 后台写线程的统计信息快照，包括[`WriteLocal::trigger_counts`]、[`WriteLocal::overflow_counts`]
+    /// 和[`WriteLocal::memory_stats`]返回的内容
+    pub fn stats(&self) -> Stats {
+        let queued = self
+            .inner
+            .workers
+            .iter()
+            .map(|worker| worker.tx.len())
+            .sum();
+        self.inner.counters.stats(queued)
+    }
+
+    /// 后台写线程中缓存的待写数据占用的内存情况
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.inner.counters.memory_stats()
+    }
+
+    /// 设置写入`dest_file`的超时时间，覆盖[`Builder::slow_write_timeout`]的设置
+    pub fn set_slow_write_timeout(&self, dest_file: PathBuf, timeout: Duration) {
+        self.inner
+            .configure(dest_file, PathSetting::SlowWriteTimeout(timeout));
+    }
+
+    /// 设置`dest_file`写入后要确保持久化到什么程度，覆盖[`Builder::durability`]的设置
+    ///
+    /// 对尚未写入的数据也生效
+    pub fn set_durability(&self, dest_file: PathBuf, durability: Durability) {
+        let setting = PathSetting::Durability(durability);
+        self.inner.configure(dest_file, setting);
+    }
+
+    /// 各种原因([`FlushTrigger`])触发写入的次数
+    pub fn trigger_counts(&self) -> TriggerCounts {
+        self.inner.counters.trigger_counts()
+    }
+
+    /// channel已满时，各种[`OverflowPolicy`]处理过的数据条数
+    pub fn overflow_counts(&self) -> OverflowCounts {
+        self.inner.counters.overflow_counts()
+    }
+
+    /// 关闭后台写线程：写完在此之前发送的所有数据后，等待后台线程退出，并返回最后一轮写入的汇总结果
+    ///
+    /// 只有第一次调用会返回`Some`，之后(包括其它克隆出来的`WriteLocal`)再调用都返回`None`，
+    /// 关闭之后再发送的数据都会被丢弃
+    pub fn shutdown(&self) -> Option<FlushSummary> {
+        self.inner.shutdown()
+    }
+}
+
+impl Inner {
+    /// 先通知所有后台写线程退出，再逐个等待，多个线程可以同时写完各自剩余的数据
+    fn shutdown(&self) -> Option<FlushSummary> {
+        let handles = self.take_handles()?;
+
+        for worker in &self.workers {
+            let _ = self.send(worker, Command::Shutdown(None));
+        }
+        let mut summary = FlushSummary::default();
+        let mut panicked = false;
+        for handle in handles {
+            match handle.join() {
+                Ok(worker_summary) => summary = summary.merge(worker_summary),
+                Err(_) => {
+                    error!("write local thread panicked");
+                    panicked = true;
                 }
             }
         }
+        self.closed();
+        (!panicked).then_some(summary)
+    }
+}
+
+impl Inner {
+    /// 写入`path`的数据交给哪个后台写线程，按路径的哈希值分配，同一个文件的数据总是按顺序写入
+    fn worker(&self, path: &Path) -> &Worker {
+        if self.workers.len() == 1 {
+            return &self.workers[0];
+        }
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        &self.workers[(hasher.finish() % self.workers.len() as u64) as usize]
+    }
+
+    /// 写入消息交给哪个后台写线程
+    fn route(&self, cmd: &Command) -> &Worker {
+        match cmd {
+            Command::Write(path, ..) | Command::Configure(path, _) | Command::Transfer(path, _) => {
+                self.worker(path)
+            }
+            _ => &self.workers[0],
+        }
+    }
+
+    fn configure(&self, path: PathBuf, setting: PathSetting) {
+        let worker = self.worker(&path);
+        let _ = self.send(worker, Command::Configure(path, setting));
+    }
+
+    /// 发送消息，channel已满时阻塞等待，不会丢弃数据。
+    /// 使用[`OverflowPolicy::DropOldest`]时持有锁等待，期间发送给同一后台写线程的其它消息也要等待
+    fn send(&self, worker: &Worker, cmd: Command) -> Result<(), SendError<Command>> {
+        let _guard = self.order_guard(worker);
+        worker.tx.send(cmd)
+    }
 
-        // 尽管data部分在每次写入完成之后都会被清空，
-        // 但由于是iter_mut()而不是直接删除HashMap中的所有元素，所以总是存在元素而进入for的迭代，
-        // 因此loop的开头部分需通过阻塞的方式等待可写数据
-        for (f, data) in cached.iter_mut() {
-            // 某个文件接收到数据后，其它缓存的路径下可能没有要写的数据，因此跳过空的
-            if data.is_empty() {
-                continue;
+    /// 使用[`OverflowPolicy::DropOldest`]时发送数据：channel已满时一次取出其中所有的消息，
+    /// 丢弃最早的一条数据后按原来的顺序放回，flush、设置等消息保持原来的位置。
+    /// 发送给该线程的消息都持有`oldest`锁发送，不会插入到取出又放回的消息之前
+    ///
+    /// channel中只有flush等消息时无法腾出位置，返回[`TrySendError::Full`]由调用者等待
+    fn send_drop_oldest(
+        &self,
+        worker: &Worker,
+        mut cmd: Command,
+    ) -> Result<(), TrySendError<Command>> {
+        let oldest = worker.oldest.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            cmd = match worker.tx.try_send(cmd) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(cmd)) => cmd,
+                Err(e) => return Err(e),
+            };
+            let Some(rx) = oldest.as_ref() else {
+                return Err(TrySendError::Disconnected(cmd));
+            };
+            if worker.waiting.load(Ordering::Acquire) > 0 {
+                return Err(TrySendError::Full(cmd));
             }
-            match data {
-                WriteData::Override(data) => match write(f, &data) {
-                    Err(e) => error!("{e}"),
-                    Ok(_) => info!("override {} bytes to {:?}", data.len(), f.as_os_str()),
-                },
-                WriteData::Append(data) => {
-                    let file = OpenOptions::new().append(true).create(true).open(f);
-                    match file {
-                        Err(e) => error!("{e}"),
-                        Ok(mut file) => match file.write(data) {
-                            Ok(n) => info!("append {n} bytes to {:?}", f.as_os_str()),
-                            Err(e) => error!("{e}"),
-                        },
+            let mut queued: Vec<_> = rx.drain().collect();
+            match queued.iter().position(|c| matches!(c, Command::Write(..))) {
+                Some(i) => {
+                    if let Command::Write(f, _, ack) = queued.remove(i) {
+                        self.counters.overflowed(&self.overflow);
+                        warn!("write local channel full, drop oldest data for {:?}", f);
+                        if let Some(ack) = ack {
+                            let _ = ack.send(Err(WriteError::Dropped));
+                        }
+                    }
+                }
+                // 刚好被后台写线程取走了
+                None if queued.is_empty() => continue,
+                None => {
+                    for queued in queued {
+                        let _ = worker.tx.send(queued);
                     }
+                    return Err(TrySendError::Full(cmd));
                 }
             }
-            // 本次数据写完之后清空
-            data.clear();
+            // 放回的都是取出的消息，channel一定有空位，不会阻塞
+            for queued in queued {
+                let _ = worker.tx.send(queued);
+            }
         }
     }
+
+    /// 使用[`OverflowPolicy::DropOldest`]时，不经过[`Inner::send_drop_oldest`]发送也要持有的锁
+    fn order_guard<'a>(
+        &self,
+        worker: &'a Worker,
+    ) -> Option<MutexGuard<'a, Option<Receiver<Command>>>> {
+        (self.overflow == OverflowPolicy::DropOldest)
+            .then(|| worker.oldest.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// 只有第一个关闭后台写线程的调用者能拿到`JoinHandle`
+    fn take_handles(&self) -> Option<Vec<JoinHandle<FlushSummary>>> {
+        let handles: Vec<_> = self
+            .workers
+            .iter()
+            .filter_map(|worker| {
+                worker
+                    .handle
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take()
+            })
+            .collect();
+        (!handles.is_empty()).then_some(handles)
+    }
+
+    /// 后台写线程退出后，不再有人接收消息，之后的发送都会失败
+    fn closed(&self) {
+        for worker in &self.workers {
+            worker
+                .oldest
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// 一轮写入的汇总结果
+#[derive(Debug, Default, Clone)]
+pub struct FlushSummary {
+    /// 写入成功的文件及写入的字节数
+    pub written: Vec<(PathBuf, usize)>,
+    /// 写入失败的文件及失败原因
+    pub failed: Vec<(PathBuf, Arc<io::Error>)>,
+}
+
+impl FlushSummary {
+    /// 合并多个后台写线程的汇总结果
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        self.written.extend(other.written);
+        self.failed.extend(other.failed);
+        self
+    }
+
+    /// 只保留`path`的写入结果
+    pub(crate) fn only(&self, path: &Path) -> Self {
+        Self {
+            written: self
+                .written
+                .iter()
+                .filter(|(f, _)| f == path)
+                .cloned()
+                .collect(),
+            failed: self
+                .failed
+                .iter()
+                .filter(|(f, _)| f == path)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// 待写入本地的(字节)数据是要追加的、截断覆盖原有数据的，还是写到文件指定位置的
+///
+/// 数据可以由`Vec<u8>`、`Arc<[u8]>`、`&'static [u8]`等转换为[`Payload`]，见[`Payload`]
+#[derive(Debug)]
+pub enum WriteData {
+    Append(Payload),
+    Override(Payload),
+    /// 从文件的`offset`处开始覆盖写入(pwrite)，不截断文件，超出文件末尾时扩展文件，
+    /// 适用于索引、位图等固定布局的文件
+    ///
+    /// 同一轮中写到同一文件的多个随机写入会合并，范围重叠的部分以后到的为准。
+    /// 与追加写入交替时按到达顺序分轮写入。随机写入的数据不压缩，也不触发按大小轮转
+    WriteAt {
+        offset: u64,
+        data: Payload,
+    },
+}
+
+impl WriteData {
+    pub(crate) fn payload(&self) -> &Payload {
+        match self {
+            WriteData::Append(data)
+            | WriteData::Override(data)
+            | WriteData::WriteAt { data, .. } => data,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.payload().len()
+    }
 }
@@ -0,0 +1,144 @@
+use crate::{payload::Payload, WriteData};
+
+/// 后台写线程中某个文件合并后尚未写入的数据
+pub(crate) enum Merged {
+    Append(Payload),
+    Override(Payload),
+    /// 按偏移量排列、互不重叠的随机写入
+    WriteAt(Vec<(u64, Payload)>),
+}
+
+impl From<WriteData> for Merged {
+    fn from(data: WriteData) -> Self {
+        match data {
+            WriteData::Append(data) => Merged::Append(data),
+            WriteData::Override(data) => Merged::Override(data),
+            WriteData::WriteAt { offset, data } => Merged::WriteAt(vec![(offset, data)]),
+        }
+    }
+}
+
+impl Merged {
+    /// 能否按到达顺序合并后一批数据`next`，合并结果与依次写入这些数据相同
+    ///
+    /// 追加写入和随机写入交替时，随机写入的位置可能与追加的数据重叠，也可能扩展了文件使追加的位置后移，
+    /// 无法合并，只能等前面的数据写完后再写入
+    pub(crate) fn can_merge(&self, next: &Merged) -> bool {
+        matches!(
+            (self, next),
+            (_, Merged::Override(_))
+                | (Merged::Append(_) | Merged::Override(_), Merged::Append(_))
+                | (Merged::WriteAt(_), Merged::WriteAt(_))
+        )
+    }
+
+    /// 按到达顺序合并同一文件的后一批数据，需要先通过[`Merged::can_merge`]确认可以合并：
+    /// 覆盖类型的数据直接替换已有数据，且合并结果变为覆盖类型；
+    /// 追加类型的数据直接追加在尾部，合并结果的类型保持不变；
+    /// 随机写入的数据与已有的随机写入重叠时，重叠部分以后到的为准
+    ///
+    /// 例如先追加A、再覆盖B、再追加C，合并结果为覆盖写入B+C
+    pub(crate) fn merge(&mut self, next: Merged) {
+        match (self, next) {
+            (this, next @ Merged::Override(_)) => *this = next,
+            (Merged::Append(local) | Merged::Override(local), Merged::Append(data)) => {
+                local.append(data)
+            }
+            (Merged::WriteAt(local), Merged::WriteAt(ranges)) => {
+                for (offset, data) in ranges {
+                    insert_range(local, offset, data);
+                }
+            }
+            _ => unreachable!("merge data that can not be merged"),
+        }
+    }
+
+    /// 追加或覆盖写入的数据，随机写入时返回`None`
+    pub(crate) fn payload_mut(&mut self) -> Option<&mut Payload> {
+        match self {
+            Merged::Append(data) | Merged::Override(data) => Some(data),
+            Merged::WriteAt(_) => None,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Merged::Append(data) | Merged::Override(data) => data.len(),
+            Merged::WriteAt(ranges) => ranges.iter().map(|(_, data)| data.len()).sum(),
+        }
+    }
+
+    /// 是否没有要写入的数据。空的覆盖写入要清空文件，不是空的
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            Merged::Override(_) => false,
+            _ => self.len() == 0,
+        }
+    }
+
+    /// 独占的数据占用的内存
+    pub(crate) fn capacity(&self) -> usize {
+        match self {
+            Merged::Append(data) | Merged::Override(data) => data.capacity(),
+            Merged::WriteAt(ranges) => ranges.iter().map(|(_, data)| data.capacity()).sum(),
+        }
+    }
+
+    /// 拆回原来的形式，随机写入的每一段各是一份。开头已压缩的`compressed`个字节与之后尚未压缩的数据
+    /// 也各是一份，返回各份数据及其是否已压缩
+    pub(crate) fn into_write_data(self, compressed: usize) -> Vec<(WriteData, bool)> {
+        let (mut head, is_override) = match self {
+            Merged::Append(data) => (data, false),
+            Merged::Override(data) => (data, true),
+            Merged::WriteAt(ranges) => {
+                return ranges
+                    .into_iter()
+                    .map(|(offset, data)| (WriteData::WriteAt { offset, data }, false))
+                    .collect()
+            }
+        };
+        let tail = head.split_off(compressed.min(head.len()));
+        // 覆盖写入只有第一份是覆盖的，之后的一份追加在它后面
+        let kind = |data, first: bool| match is_override && first {
+            true => WriteData::Override(data),
+            false => WriteData::Append(data),
+        };
+        let mut all = Vec::with_capacity(2);
+        if !head.is_empty() {
+            all.push((kind(head, true), true));
+        }
+        if !tail.is_empty() || all.is_empty() {
+            let first = all.is_empty();
+            all.push((kind(tail, first), false));
+        }
+        all
+    }
+}
+
+/// 将从`offset`开始的`data`插入`ranges`，已有数据中与之重叠的部分被丢弃
+fn insert_range(ranges: &mut Vec<(u64, Payload)>, offset: u64, data: Payload) {
+    let end = offset + data.len() as u64;
+    let mut merged = Vec::with_capacity(ranges.len() + 1);
+    for (start, mut local) in ranges.drain(..) {
+        let local_end = start + local.len() as u64;
+        if local_end <= offset || start >= end {
+            merged.push((start, local));
+            continue;
+        }
+        // 保留重叠部分之前和之后的数据
+        let mut start = start;
+        if start < offset {
+            let rest = local.split_off((offset - start) as usize);
+            merged.push((start, local));
+            local = rest;
+            start = offset;
+        }
+        if local_end > end {
+            let tail = local.split_off((end - start) as usize);
+            merged.push((end, tail));
+        }
+    }
+    merged.push((offset, data));
+    merged.sort_by_key(|(offset, _)| *offset);
+    *ranges = merged;
+}
@@ -0,0 +1,78 @@
+use crate::WriteData;
+use std::{fmt, path::PathBuf};
+
+/// channel已满时，[`WriteLocal::write`](crate::WriteLocal::write)和
+/// [`WriteLocal::write_with_ack`](crate::WriteLocal::write_with_ack)如何处理新数据
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 阻塞等待，直到channel有空位
+    #[default]
+    Block,
+    /// 丢弃新数据
+    DropNewest,
+    /// 丢弃channel中最早的数据，腾出位置给新数据
+    DropOldest,
+    /// 将新数据写入该溢出目录，见[`WriteLocal::builder`](crate::WriteLocal::builder)
+    Spill(PathBuf),
+}
+
+/// 各种溢出处理方式处理过的数据条数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverflowCounts {
+    /// 因[`OverflowPolicy::DropNewest`]被丢弃的条数
+    pub dropped_newest: u64,
+    /// 因[`OverflowPolicy::DropOldest`]被丢弃的条数
+    pub dropped_oldest: u64,
+    /// 因[`OverflowPolicy::Spill`]被写入溢出目录的条数
+    pub spilled: u64,
+}
+
+/// [`WriteLocal::try_write`](crate::WriteLocal::try_write)和
+/// [`WriteLocal::write_timeout`](crate::WriteLocal::write_timeout)未能发送数据时，将数据原样返回
+pub enum SubmitError {
+    /// channel已满
+    Full(PathBuf, WriteData),
+    /// 等待超时后channel仍然是满的
+    Timeout(PathBuf, WriteData),
+    /// 后台写线程已关闭
+    Closed(PathBuf, WriteData),
+}
+
+impl SubmitError {
+    /// 取回未能发送的路径和数据
+    pub fn into_inner(self) -> (PathBuf, WriteData) {
+        match self {
+            SubmitError::Full(f, d) | SubmitError::Timeout(f, d) | SubmitError::Closed(f, d) => {
+                (f, d)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, path, data) = match self {
+            SubmitError::Full(p, d) => ("Full", p, d),
+            SubmitError::Timeout(p, d) => ("Timeout", p, d),
+            SubmitError::Closed(p, d) => ("Closed", p, d),
+        };
+        f.debug_struct(kind)
+            .field("path", path)
+            .field("bytes", &data.len())
+            .finish()
+    }
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Full(p, _) => write!(f, "write local channel full, {:?} not sent", p),
+            SubmitError::Timeout(p, _) => {
+                write!(f, "timed out sending {:?} to write local channel", p)
+            }
+            SubmitError::Closed(p, _) => write!(f, "write local thread closed, {:?} not sent", p),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
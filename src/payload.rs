@@ -0,0 +1,242 @@
+use std::{fmt, io::IoSlice, ops::Range, sync::Arc};
+
+/// 小于该字节数的数据合并时复制到上一段数据的末尾，避免向量写入时分段过多
+const COALESCE_BYTES: usize = 4096;
+
+/// 一次向量写入最多提交的分段数，与Linux的IOV_MAX相同
+const MAX_IO_SLICES: usize = 1024;
+
+/// 要写入的数据，由一段或多段数据组成
+///
+/// 可以由`Vec<u8>`、`Arc<[u8]>`、`&'static [u8]`以及启用`bytes`特性后的`bytes::Bytes`转换得到。
+/// 同一文件的多批数据合并时只是将各段数据串起来，写入时通过向量写入一次提交，不会再复制一遍。
+/// 只有较小的数据才会被复制合并到一起
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use write_local::{Payload, WriteData};
+/// let shared: Arc<[u8]> = Arc::from(&b"shared"[..]);
+/// let mut payload = Payload::from(shared.clone());
+/// payload.push(&b" and static"[..]);
+/// assert_eq!(payload.len(), 17);
+/// let data = WriteData::Append(payload);
+/// ```
+#[derive(Default)]
+pub struct Payload {
+    chunks: Vec<Chunk>,
+    len: usize,
+}
+
+/// 一段数据，`Shared`只引用其中的一部分
+enum Chunk {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>, Range<usize>),
+    Static(&'static [u8]),
+    #[cfg(feature = "bytes")]
+    Bytes(bytes::Bytes),
+}
+
+impl Chunk {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Chunk::Owned(data) => data,
+            Chunk::Shared(data, range) => &data[range.clone()],
+            Chunk::Static(data) => data,
+            #[cfg(feature = "bytes")]
+            Chunk::Bytes(data) => data,
+        }
+    }
+
+    /// 丢弃开头的`n`个字节
+    fn advance(&mut self, n: usize) {
+        match self {
+            Chunk::Owned(data) => {
+                data.drain(..n);
+            }
+            Chunk::Shared(_, range) => range.start += n,
+            Chunk::Static(data) => *data = &data[n..],
+            #[cfg(feature = "bytes")]
+            Chunk::Bytes(data) => {
+                let _ = data.split_to(n);
+            }
+        }
+    }
+
+    /// 拆分出`at`之后的数据，自身只保留前`at`个字节
+    fn split_off(&mut self, at: usize) -> Chunk {
+        match self {
+            Chunk::Owned(data) => Chunk::Owned(data.split_off(at)),
+            Chunk::Shared(data, range) => {
+                let mid = range.start + at;
+                let tail = Chunk::Shared(data.clone(), mid..range.end);
+                range.end = mid;
+                tail
+            }
+            Chunk::Static(data) => {
+                let (head, tail) = data.split_at(at);
+                *data = head;
+                Chunk::Static(tail)
+            }
+            #[cfg(feature = "bytes")]
+            Chunk::Bytes(data) => Chunk::Bytes(data.split_off(at)),
+        }
+    }
+}
+
+impl Payload {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn from_chunk(chunk: Chunk) -> Self {
+        let len = chunk.as_slice().len();
+        let chunks = if len > 0 { vec![chunk] } else { Vec::new() };
+        Self { chunks, len }
+    }
+
+    /// 在末尾追加一段数据
+    pub fn push(&mut self, data: impl Into<Payload>) {
+        self.append(data.into());
+    }
+
+    /// 总字节数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 依次返回各段数据
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.chunks.iter().map(Chunk::as_slice)
+    }
+
+    /// 将各段数据复制到一起
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.len);
+        for chunk in self.chunks() {
+            data.extend_from_slice(chunk);
+        }
+        data
+    }
+
+    /// 将`other`的各段数据移到末尾，较小的数据复制到最后一段独占的数据中
+    pub(crate) fn append(&mut self, other: Payload) {
+        self.len += other.len;
+        for chunk in other.chunks {
+            let small = chunk.as_slice().len() < COALESCE_BYTES;
+            match self.chunks.last_mut() {
+                Some(Chunk::Owned(last)) if small && last.len() < COALESCE_BYTES => {
+                    last.extend_from_slice(chunk.as_slice())
+                }
+                _ if chunk.as_slice().is_empty() => {}
+                _ => self.chunks.push(chunk),
+            }
+        }
+    }
+
+    /// 拆分出`at`之后的数据，自身只保留前`at`个字节
+    pub(crate) fn split_off(&mut self, at: usize) -> Payload {
+        let mut tail = Payload::new();
+        let mut offset = 0;
+        let mut index = 0;
+        while index < self.chunks.len() {
+            let len = self.chunks[index].as_slice().len();
+            if offset + len > at {
+                break;
+            }
+            offset += len;
+            index += 1;
+        }
+        if offset < at {
+            let rest = self.chunks[index].split_off(at - offset);
+            index += 1;
+            tail.chunks.push(rest);
+        }
+        tail.chunks.extend(self.chunks.drain(index..));
+        tail.len = self.len - at;
+        self.len = at;
+        tail
+    }
+
+    /// 丢弃开头已经写入的`n`个字节
+    pub(crate) fn advance(&mut self, mut n: usize) {
+        self.len -= n;
+        let mut done = 0;
+        for chunk in &mut self.chunks {
+            let len = chunk.as_slice().len();
+            if len > n {
+                chunk.advance(n);
+                break;
+            }
+            n -= len;
+            done += 1;
+        }
+        self.chunks.drain(..done);
+    }
+
+    /// 从第`skip`个字节开始的各段数据，用于一次向量写入
+    pub(crate) fn io_slices(&self, mut skip: usize) -> Vec<IoSlice<'_>> {
+        let mut slices = Vec::with_capacity(self.chunks.len().min(MAX_IO_SLICES));
+        for chunk in self.chunks() {
+            if slices.len() == MAX_IO_SLICES {
+                break;
+            }
+            if skip >= chunk.len() {
+                skip -= chunk.len();
+                continue;
+            }
+            slices.push(IoSlice::new(&chunk[skip..]));
+            skip = 0;
+        }
+        slices
+    }
+
+    /// 独占的数据占用的内存
+    pub(crate) fn capacity(&self) -> usize {
+        self.chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Chunk::Owned(data) => data.capacity(),
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Payload")
+            .field("len", &self.len)
+            .field("chunks", &self.chunks.len())
+            .finish()
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(data: Vec<u8>) -> Self {
+        Self::from_chunk(Chunk::Owned(data))
+    }
+}
+
+impl From<Arc<[u8]>> for Payload {
+    fn from(data: Arc<[u8]>) -> Self {
+        let range = 0..data.len();
+        Self::from_chunk(Chunk::Shared(data, range))
+    }
+}
+
+impl From<&'static [u8]> for Payload {
+    fn from(data: &'static [u8]) -> Self {
+        Self::from_chunk(Chunk::Static(data))
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<bytes::Bytes> for Payload {
+    fn from(data: bytes::Bytes) -> Self {
+        Self::from_chunk(Chunk::Bytes(data))
+    }
+}
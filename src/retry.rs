@@ -0,0 +1,127 @@
+use crate::WriteData;
+use std::{fmt, io, path::PathBuf, sync::Arc, time::Duration};
+
+/// 写入失败时的重试策略
+///
+/// 写入失败的数据保留在后台写线程中，等待一段时间后重试，每次重试的等待时间按`multiplier`倍增长，
+/// 最长不超过`max_backoff`。重试次数用完或者错误不可重试时，数据交给
+/// [`Builder::dead_letter`](crate::Builder::dead_letter)设置的回调
+///
+/// ```
+/// # use std::{io, time::Duration};
+/// # use write_local::RetryPolicy;
+/// let policy = RetryPolicy::default()
+///     .max_attempts(10)
+///     .backoff(Duration::from_millis(50), Duration::from_secs(30))
+///     .retry_if(|e| e.kind() != io::ErrorKind::InvalidInput);
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: u32,
+    retry_if: Arc<dyn Fn(&io::Error) -> bool + Send + Sync>,
+}
+
+impl Default for RetryPolicy {
+    /// 最多尝试5次，重试间隔从100ms开始倍增，最长10秒，所有错误都重试
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2,
+            retry_if: Arc::new(|_| true),
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("multiplier", &self.multiplier)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+    /// 不重试，第一次写入失败就放弃
+    pub fn never() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// 最多尝试写入多少次(包括第一次)，至少为1
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// 第一次重试前等待`initial`，之后每次的等待时间倍增，最长不超过`max`
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// 每次重试的等待时间是上一次的多少倍，默认2倍
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier.max(1);
+        self
+    }
+
+    /// 根据错误决定是否重试，返回`false`时直接放弃
+    pub fn retry_if(mut self, f: impl Fn(&io::Error) -> bool + Send + Sync + 'static) -> Self {
+        self.retry_if = Arc::new(f);
+        self
+    }
+
+    /// 已经尝试了`attempts`次且最近一次失败的原因是`err`时，是否还要重试
+    pub(crate) fn should_retry(&self, err: &io::Error, attempts: u32) -> bool {
+        attempts < self.max_attempts && (self.retry_if)(err)
+    }
+
+    /// 已经尝试了`attempts`次后，下次重试前要等待多久
+    pub(crate) fn backoff_after(&self, attempts: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(attempts.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// 最终未能写入的数据
+pub struct DeadLetter {
+    /// 要写入的文件
+    pub path: PathBuf,
+    /// 未能写入的数据，追加写入时只包含未写入的部分，合并后的随机写入每一段各回调一次
+    pub data: WriteData,
+    /// 最近一次写入失败的原因
+    pub error: Arc<io::Error>,
+    /// 共尝试写入了多少次
+    pub attempts: u32,
+}
+
+impl fmt::Debug for DeadLetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetter")
+            .field("path", &self.path)
+            .field("bytes", &self.data.len())
+            .field("error", &self.error)
+            .field("attempts", &self.attempts)
+            .finish()
+    }
+}
+
+/// 处理最终未能写入的数据的回调，在后台写线程中调用
+#[derive(Clone)]
+pub(crate) struct DeadLetterHandler(pub(crate) Arc<dyn Fn(DeadLetter) + Send + Sync>);
+
+impl fmt::Debug for DeadLetterHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DeadLetterHandler")
+    }
+}
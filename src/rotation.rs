@@ -0,0 +1,448 @@
+use crate::sink::Sink;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+/// 追加写入的文件的轮转策略，见[`WriteLocal::set_rotation`](crate::WriteLocal::set_rotation)
+///
+/// 后台写线程在两轮写入之间检查是否需要轮转：文件大小加上本轮要追加的数据超出`max_size`，
+/// 或者进入了新的小时/天时，将文件改名为按`template`生成的名称，之后的数据写入新建的同名文件
+///
+/// `template`中可以使用以下占位符，默认为`{name}.{date}.{index}`，例如`app.log.2026-10-15.1`：
+/// - `{name}`：原文件名
+/// - `{date}`：日期，格式为`YYYY-MM-DD`
+/// - `{hour}`：小时，格式为`HH`
+/// - `{index}`：从1开始的序号，用于避免与已有的文件重名。模板中没有序号时，重名的文件名末尾会加上`.{index}`
+///
+/// 日期和小时均为UTC时间。按时间轮转时，使用的是被轮转出去的数据所属的日期和小时
+///
+/// ```
+/// # use write_local::{RotationInterval, RotationPolicy};
+/// let policy = RotationPolicy::default()
+///     .max_size(100 * 1024 * 1024)
+///     .interval(RotationInterval::Daily)
+///     .keep(7);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    max_size: Option<u64>,
+    interval: Option<RotationInterval>,
+    keep: Option<usize>,
+    template: String,
+}
+
+/// 按时间轮转的周期
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationInterval {
+    Hourly,
+    Daily,
+}
+
+impl RotationInterval {
+    fn secs(self) -> u64 {
+        match self {
+            RotationInterval::Hourly => 3600,
+            RotationInterval::Daily => 86400,
+        }
+    }
+}
+
+impl Default for RotationPolicy {
+    /// 不轮转，需要通过[`RotationPolicy::max_size`]或[`RotationPolicy::interval`]设置轮转条件
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            interval: None,
+            keep: None,
+            template: "{name}.{date}.{index}".to_string(),
+        }
+    }
+}
+
+impl RotationPolicy {
+    /// 文件超出`bytes`字节时轮转
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// 每小时或每天轮转一次
+    pub fn interval(mut self, interval: RotationInterval) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// 最多保留多少个轮转出去的文件，超出时删除最旧的，默认全部保留
+    pub fn keep(mut self, count: usize) -> Self {
+        self.keep = Some(count);
+        self
+    }
+
+    /// 轮转出去的文件的命名模板
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+}
+
+/// 某个文件的轮转策略及其状态
+pub(crate) struct Rotation {
+    policy: RotationPolicy,
+    /// 当前文件中的数据所属的周期，即UNIX时间戳除以周期的秒数
+    period: Option<u64>,
+}
+
+impl Rotation {
+    pub(crate) fn new(policy: RotationPolicy) -> Self {
+        Self {
+            policy,
+            period: None,
+        }
+    }
+
+    pub(crate) fn into_policy(self) -> RotationPolicy {
+        self.policy
+    }
+
+    /// 在`now`即将向`path`追加`incoming`字节时，检查是否需要轮转，轮转了返回`true`
+    pub(crate) fn rotate_if_needed(
+        &mut self,
+        sink: &dyn Sink,
+        path: &Path,
+        incoming: u64,
+        now: SystemTime,
+    ) -> io::Result<bool> {
+        let now = unix_secs(now);
+        let secs = self.policy.interval.map(RotationInterval::secs);
+        let current = secs.map(|secs| now / secs);
+
+        let meta = match sink.metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.period = current;
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+        // 首次见到已有的文件时，以其最后修改时间所在的周期作为其数据所属的周期
+        let period = match (self.period, secs) {
+            (Some(period), _) => Some(period),
+            (None, Some(secs)) => meta.modified.map(|t| unix_secs(t) / secs),
+            (None, None) => None,
+        };
+
+        let len = meta.len;
+        let by_time = period.is_some() && period != current;
+        let by_size = self.policy.max_size.is_some_and(|max| len + incoming > max);
+        if len == 0 || !(by_time || by_size) {
+            self.period = period.or(current);
+            return Ok(false);
+        }
+
+        let stamp = match (by_time, period, secs) {
+            (true, Some(period), Some(secs)) => period * secs,
+            _ => now,
+        };
+        let target = self.target(sink, path, stamp);
+        sink.rename(path, &target)?;
+        info!("rotate {:?} to {:?}", path.as_os_str(), target.as_os_str());
+        self.period = current;
+
+        if let Err(e) = self.remove_old(sink, path) {
+            warn!(
+                "failed to remove old rotated files of {:?}: {e}",
+                path.as_os_str()
+            );
+        }
+        Ok(true)
+    }
+
+    /// 轮转出去的文件名，`stamp`为用于生成日期和小时的UNIX时间戳
+    fn target(&self, sink: &dyn Sink, path: &Path, stamp: u64) -> PathBuf {
+        let name = file_name(path);
+        let (year, month, day) = civil_from_days((stamp / 86400) as i64);
+        let date = format!("{year:04}-{month:02}-{day:02}");
+        let hour = format!("{:02}", stamp % 86400 / 3600);
+        let rendered = self
+            .policy
+            .template
+            .replace("{name}", &name)
+            .replace("{date}", &date)
+            .replace("{hour}", &hour);
+
+        let (prefix, suffix) = match rendered.split_once("{index}") {
+            Some((prefix, suffix)) => (prefix.to_string(), suffix),
+            // 模板中没有序号时，与已有的文件重名才在末尾加上序号
+            None => {
+                let target = path.with_file_name(&rendered);
+                if sink.metadata(&target).is_err() {
+                    return target;
+                }
+                (format!("{rendered}."), "")
+            }
+        };
+        // 序号接着已有的最大序号，避免删除旧文件后重复使用较小的序号
+        let mut index = max_index(sink, path, &prefix, suffix) + 1;
+        loop {
+            let target = path.with_file_name(format!("{prefix}{index}{suffix}"));
+            if sink.metadata(&target).is_err() {
+                return target;
+            }
+            index += 1;
+        }
+    }
+
+    /// 只保留最新的`keep`个轮转出去的文件
+    fn remove_old(&self, sink: &dyn Sink, path: &Path) -> io::Result<()> {
+        let Some(keep) = self.policy.keep else {
+            return Ok(());
+        };
+        let dir = parent(path);
+        let name = file_name(path);
+        let pattern = Pattern::new(&self.policy.template, &name);
+
+        let mut rotated = Vec::new();
+        for entry in sink.read_dir(dir)? {
+            let entry_name = file_name(&entry);
+            if entry_name != name && pattern.matches(&entry_name) {
+                let modified = sink.metadata(&entry)?.modified;
+                rotated.push((modified, entry_name.len(), entry_name, entry));
+            }
+        }
+        if rotated.len() <= keep {
+            return Ok(());
+        }
+
+        // 修改时间相同时，按序号从小到大排列
+        rotated.sort();
+        for (_, _, _, old) in &rotated[..rotated.len() - keep] {
+            sink.remove(old)?;
+            info!("remove old rotated file {:?}", old.as_os_str());
+        }
+        Ok(())
+    }
+}
+
+/// 目录中形如`{prefix}{序号}{suffix}`的文件的最大序号
+fn max_index(sink: &dyn Sink, path: &Path, prefix: &str, suffix: &str) -> u64 {
+    let Ok(entries) = sink.read_dir(parent(path)) else {
+        return 0;
+    };
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let name = entry.file_name()?.to_str()?;
+            name.strip_prefix(prefix)?
+                .strip_suffix(suffix)?
+                .parse()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 将1970-01-01以来的天数转换为年月日
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 由命名模板生成的匹配规则，`{name}`替换为原文件名，其它占位符匹配任意非空内容
+struct Pattern {
+    parts: Vec<Option<String>>,
+}
+
+impl Pattern {
+    fn new(template: &str, name: &str) -> Self {
+        let template = template.replace("{name}", name);
+        let mut parts = Vec::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            if start > 0 {
+                parts.push(Some(rest[..start].to_string()));
+            }
+            parts.push(None);
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Some(rest.to_string()));
+        }
+        Self { parts }
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        matches(&self.parts, s)
+    }
+}
+
+fn matches(parts: &[Option<String>], s: &str) -> bool {
+    match parts.split_first() {
+        None => s.is_empty(),
+        Some((Some(literal), rest)) => s
+            .strip_prefix(literal.as_str())
+            .is_some_and(|s| matches(rest, s)),
+        Some((None, rest)) => (1..=s.len())
+            .filter(|&i| s.is_char_boundary(i))
+            .any(|i| matches(rest, &s[i..])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemorySink;
+    use std::time::Duration;
+
+    /// 2026-10-15 23:30:00 UTC
+    const STAMP: u64 = 1_792_107_000;
+
+    fn touch(sink: &MemorySink, path: &str) {
+        sink.create(Path::new(path)).unwrap().append(b"x").unwrap();
+    }
+
+    #[test]
+    fn civil_from_days_handles_leap_years_and_negative_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(20741), (2026, 10, 15));
+    }
+
+    #[test]
+    fn pattern_matches_rendered_names_only() {
+        let pattern = Pattern::new("{name}.{date}.{index}", "app.log");
+        assert!(pattern.matches("app.log.2026-10-15.1"));
+        assert!(pattern.matches("app.log.2026-10-15.12"));
+        assert!(!pattern.matches("app.log"));
+        assert!(!pattern.matches("app.log.2026-10-15"));
+        assert!(!pattern.matches("other.log.2026-10-15.1"));
+
+        let pattern = Pattern::new("old/{name}-{hour}", "app.log");
+        assert!(pattern.matches("old/app.log-23"));
+        assert!(!pattern.matches("app.log-23"));
+        // 占位符匹配非ASCII内容时不会切在字符中间
+        assert!(Pattern::new("{name}.{date}", "日志").matches("日志.十月"));
+    }
+
+    #[test]
+    fn max_index_only_counts_matching_names() {
+        let sink = MemorySink::default();
+        let path = Path::new("logs/app.log");
+        assert_eq!(max_index(&sink, path, "app.log.", ""), 0);
+        for name in [
+            "app.log.3",
+            "app.log.10",
+            "app.log.x",
+            "app.log.2.gz",
+            "other/app.log.20",
+        ] {
+            touch(&sink, &format!("logs/{name}"));
+        }
+        assert_eq!(max_index(&sink, path, "app.log.", ""), 10);
+        assert_eq!(max_index(&sink, path, "app.log.", ".gz"), 2);
+    }
+
+    #[test]
+    fn target_skips_existing_names() {
+        let sink = MemorySink::default();
+        let path = Path::new("app.log");
+        let rotation = Rotation::new(RotationPolicy::default());
+        assert_eq!(
+            rotation.target(&sink, path, STAMP),
+            Path::new("app.log.2026-10-15.1")
+        );
+        // 删除了较小序号的文件后，仍接着最大的序号
+        touch(&sink, "app.log.2026-10-15.2");
+        assert_eq!(
+            rotation.target(&sink, path, STAMP),
+            Path::new("app.log.2026-10-15.3")
+        );
+
+        let rotation = Rotation::new(RotationPolicy::default().template("{name}.{date}-{hour}"));
+        let target = rotation.target(&sink, path, STAMP);
+        assert_eq!(target, Path::new("app.log.2026-10-15-23"));
+        touch(&sink, "app.log.2026-10-15-23");
+        touch(&sink, "app.log.2026-10-15-23.1");
+        assert_eq!(
+            rotation.target(&sink, path, STAMP),
+            Path::new("app.log.2026-10-15-23.2")
+        );
+    }
+
+    #[test]
+    fn remove_old_keeps_newest() {
+        let sink = MemorySink::default();
+        let rotation = Rotation::new(RotationPolicy::default().keep(2));
+        for name in [
+            "app.log.2026-10-14.1",
+            "app.log.2026-10-15.2",
+            "app.log.2026-10-15.10",
+        ] {
+            touch(&sink, name);
+            // 修改时间的精度可能不足以区分先后创建的文件
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        touch(&sink, "app.log");
+        touch(&sink, "other.log.2026-10-13.1");
+        rotation.remove_old(&sink, Path::new("app.log")).unwrap();
+        let expected = [
+            "app.log",
+            "app.log.2026-10-15.10",
+            "app.log.2026-10-15.2",
+            "other.log.2026-10-13.1",
+        ];
+        assert_eq!(sink.paths(), expected.map(PathBuf::from));
+    }
+
+    #[test]
+    fn rotates_by_the_given_time() {
+        let sink = MemorySink::default();
+        let path = Path::new("app.log");
+        let policy = RotationPolicy::default().interval(RotationInterval::Daily);
+        let mut rotation = Rotation::new(policy);
+        let now = UNIX_EPOCH + Duration::from_secs(STAMP);
+        assert!(!rotation.rotate_if_needed(&sink, path, 1, now).unwrap());
+        touch(&sink, "app.log");
+        assert!(!rotation.rotate_if_needed(&sink, path, 1, now).unwrap());
+
+        // 进入新的一天后轮转，文件名使用数据所属的前一天
+        let tomorrow = now + Duration::from_secs(1800);
+        assert!(rotation.rotate_if_needed(&sink, path, 1, tomorrow).unwrap());
+        assert_eq!(sink.paths(), [PathBuf::from("app.log.2026-10-15.1")]);
+    }
+}
@@ -0,0 +1,571 @@
+use crate::payload::Payload;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, IoSlice},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::SystemTime,
+};
+
+/// 后台写线程写入数据的存储后端，默认为[`FsSink`]，可以通过[`Builder::sink`](crate::Builder::sink)替换
+///
+/// 后台写线程只通过这些方法访问存储：追加写入通过[`Sink::open`]打开后不断[`SinkFile::append`]，
+/// 覆盖写入通过[`Sink::create`]新建临时文件写入后[`Sink::rename`]覆盖目标文件
+/// (关闭[`Builder::atomic_override`](crate::Builder::atomic_override)时直接[`Sink::create`]目标文件)，
+/// 随机写入通过[`Sink::open_positional`]打开后[`SinkFile::write_at`]。
+/// 溢出目录仍然总是写入本地文件系统
+pub trait Sink: Send + Sync {
+    /// 以追加方式打开`path`，不存在时创建
+    fn open(&self, path: &Path) -> io::Result<Box<dyn SinkFile>>;
+
+    /// 以追加方式依次打开一轮写入中尚未缓存句柄的多个文件，结果与`paths`一一对应。
+    /// 默认逐个[`Sink::open`]
+    fn open_batch(&self, paths: &[&Path]) -> Vec<io::Result<Box<dyn SinkFile>>> {
+        paths.iter().map(|path| self.open(path)).collect()
+    }
+
+    /// 打开`path`用于随机写入，不存在时创建，不清空已有内容。默认不支持随机写入
+    fn open_positional(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        let _ = path;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// 打开`path`用于覆盖写入，不存在时创建，已存在时清空其内容
+    fn create(&self, path: &Path) -> io::Result<Box<dyn SinkFile>>;
+
+    /// 将`from`改名为`to`，`to`已存在时原子地替换它
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// 删除`path`
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// `path`的元数据，不存在时返回[`io::ErrorKind::NotFound`]
+    fn metadata(&self, path: &Path) -> io::Result<SinkMetadata>;
+
+    /// 目录`dir`中的所有文件
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// 使目录`dir`中新建、改名等操作持久化
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+
+    /// 原子覆盖写入时，将原文件`from`的权限和属主复制到临时文件`to`，`from`不存在时什么都不做。
+    /// 默认什么都不做
+    fn copy_attributes(
+        &self,
+        from: &Path,
+        to: &Path,
+        permissions: bool,
+        owner: bool,
+    ) -> io::Result<()> {
+        let _ = (from, to, permissions, owner);
+        Ok(())
+    }
+
+    /// 一轮写入中依次追加多个文件，各项的结果写入其`result`。默认逐个文件写入并同步
+    fn append_batch(&self, batch: &mut [BatchAppend<'_>]) {
+        for append in batch {
+            append.result = append_one(append);
+        }
+    }
+}
+
+/// [`Sink::append_batch`]中对一个文件的追加写入
+pub struct BatchAppend<'a> {
+    /// 由[`Sink::open`]打开的文件
+    pub file: &'a mut dyn SinkFile,
+    /// 依次追加的各段数据
+    pub bufs: &'a [IoSlice<'a>],
+    /// 写完后是否同步及同步的方式，见[`SinkFile::sync`]的`data_only`
+    pub sync: Option<bool>,
+    /// 写入的字节数，可以只写入一部分，此时可以不同步，由调用者写完剩下的数据后再同步
+    pub written: usize,
+    /// 写入或同步时的错误
+    pub result: io::Result<()>,
+}
+
+/// 逐个文件写入时，写入并同步一个文件
+pub(crate) fn append_one(append: &mut BatchAppend<'_>) -> io::Result<()> {
+    append.written = loop {
+        match append.file.append_vectored(append.bufs) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            res => break res?,
+        }
+    };
+    let len: usize = append.bufs.iter().map(|buf| buf.len()).sum();
+    match append.sync {
+        Some(data_only) if append.written == len => append.file.sync(data_only),
+        _ => Ok(()),
+    }
+}
+
+/// [`Sink`]打开的文件
+pub trait SinkFile: Send {
+    /// 在文件末尾写入`data`，返回写入的字节数，可以只写入一部分
+    fn append(&mut self, data: &[u8]) -> io::Result<usize>;
+
+    /// 在文件末尾依次写入`bufs`中的各段数据，返回写入的字节数，可以只写入一部分。
+    /// 默认只写入第一段非空的数据
+    fn append_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match bufs.iter().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.append(buf),
+            None => Ok(0),
+        }
+    }
+
+    /// 从文件的`offset`处开始写入`data`，返回写入的字节数，可以只写入一部分。
+    /// 只用于[`Sink::open_positional`]打开的文件，默认不支持
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<usize> {
+        let _ = (data, offset);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// 将缓冲的数据交给存储，见[`Durability::FlushOnly`](crate::Durability::FlushOnly)
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// 持久化写入的数据，`data_only`时可以不持久化修改时间等元数据
+    fn sync(&mut self, data_only: bool) -> io::Result<()>;
+
+    /// 文件的元数据
+    fn metadata(&self) -> io::Result<SinkMetadata>;
+
+    /// 文件描述符，供[`Sink::append_batch`]的实现直接提交系统调用，默认没有
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+/// [`Sink`]中文件的元数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkMetadata {
+    /// 文件的字节数
+    pub len: u64,
+    /// 最后修改时间
+    pub modified: Option<SystemTime>,
+    /// 文件的唯一标识，例如(dev, inode)，用于判断路径对应的是否还是打开时的那个文件。
+    /// 为`None`时每次写入都重新打开文件
+    pub id: Option<(u64, u64)>,
+}
+
+/// 通过向量写入将`data`中第`written`个字节之后的数据全部写入`file`，写入的字节数累加到`written`
+pub(crate) fn write_all(
+    file: &mut dyn SinkFile,
+    data: &Payload,
+    written: &mut usize,
+) -> io::Result<()> {
+    while *written < data.len() {
+        match file.append_vectored(&data.io_slices(*written)) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => *written += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// 后台写线程使用的[`Sink`]
+#[derive(Clone)]
+pub(crate) struct SharedSink(pub(crate) Arc<dyn Sink>);
+
+impl fmt::Debug for SharedSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedSink")
+    }
+}
+
+impl Default for SharedSink {
+    fn default() -> Self {
+        Self(Arc::new(FsSink))
+    }
+}
+
+/// 写入本地文件系统，默认的[`Sink`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsSink;
+
+pub(crate) struct FsFile(pub(crate) fs_err::File);
+
+impl Sink for FsSink {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        let file = fs_err::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?;
+        Ok(Box::new(FsFile(file)))
+    }
+
+    fn open_positional(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        let file = fs_err::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Box::new(FsFile(file)))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        Ok(Box::new(FsFile(fs_err::File::create(path)?)))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs_err::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs_err::remove_file(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<SinkMetadata> {
+        std::fs::metadata(path).map(|meta| fs_metadata(&meta))
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        fs_err::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    /// 非unix平台上无法打开目录进行fsync
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        fs_err::File::open(dir)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = dir;
+        Ok(())
+    }
+
+    fn copy_attributes(
+        &self,
+        from: &Path,
+        to: &Path,
+        permissions: bool,
+        owner: bool,
+    ) -> io::Result<()> {
+        let Ok(meta) = std::fs::metadata(from) else {
+            return Ok(());
+        };
+        if permissions {
+            fs_err::set_permissions(to, meta.permissions())?;
+        }
+        #[cfg(unix)]
+        if owner {
+            use std::os::unix::fs::MetadataExt;
+            // 非root用户通常无权修改属主，此时保留临时文件自己的属主
+            if let Err(e) = std::os::unix::fs::chown(to, Some(meta.uid()), Some(meta.gid())) {
+                tracing::warn!("failed to preserve owner of {:?}: {e}", from.as_os_str());
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = owner;
+        Ok(())
+    }
+}
+
+impl SinkFile for FsFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut self.0, data)
+    }
+
+    fn append_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        io::Write::write_vectored(&mut self.0, bufs)
+    }
+
+    #[cfg(unix)]
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::write_at(self.0.file(), data, offset)
+    }
+
+    #[cfg(windows)]
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_write(self.0.file(), data, offset)
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(self.0.file().as_raw_fd())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(&mut self.0)
+    }
+
+    fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        if data_only {
+            self.0.sync_data()
+        } else {
+            self.0.sync_all()
+        }
+    }
+
+    fn metadata(&self) -> io::Result<SinkMetadata> {
+        self.0.metadata().map(|meta| fs_metadata(&meta))
+    }
+}
+
+fn fs_metadata(meta: &std::fs::Metadata) -> SinkMetadata {
+    // 非unix平台上无法得知文件的inode，无法判断文件是否被轮转，每次写入都重新打开
+    #[cfg(unix)]
+    let id = {
+        use std::os::unix::fs::MetadataExt;
+        Some((meta.dev(), meta.ino()))
+    };
+    #[cfg(not(unix))]
+    let id = None;
+    SinkMetadata {
+        len: meta.len(),
+        modified: meta.modified().ok(),
+        id,
+    }
+}
+
+/// 写入内存的[`Sink`]，用于测试。克隆出来的`MemorySink`共享同一份数据
+///
+/// 与文件系统一样，已打开的文件被改名或删除后，写入的数据仍然进入原来的文件。
+/// 可以通过[`MemorySink::fail_open`]模拟打开文件失败
+///
+/// ```
+/// # use write_local::{MemorySink, WriteData, WriteLocal};
+/// let sink = MemorySink::default();
+/// let local = WriteLocal::builder().sink(sink.clone()).build().unwrap();
+/// local.write("app.log".into(), WriteData::Append(b"hello".to_vec().into()));
+/// local.flush();
+/// assert_eq!(sink.read("app.log").unwrap(), b"hello");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    files: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<MemoryFile>>>>>,
+    /// 打开时要返回错误的文件
+    failures: Arc<Mutex<HashMap<PathBuf, io::ErrorKind>>>,
+    /// 各个路径被打开的次数
+    opens: Arc<Mutex<HashMap<PathBuf, usize>>>,
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+    id: u64,
+    data: Vec<u8>,
+    modified: SystemTime,
+}
+
+/// [`MemorySink`]中文件的唯一标识
+static MEMORY_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+impl MemoryFile {
+    fn new() -> Self {
+        Self {
+            id: MEMORY_FILE_ID.fetch_add(1, Ordering::Relaxed),
+            data: Vec::new(),
+            modified: SystemTime::now(),
+        }
+    }
+
+    fn metadata(&self) -> SinkMetadata {
+        SinkMetadata {
+            len: self.data.len() as u64,
+            modified: Some(self.modified),
+            id: Some((0, self.id)),
+        }
+    }
+}
+
+struct MemoryHandle(Arc<Mutex<MemoryFile>>);
+
+impl MemorySink {
+    /// `path`的内容，不存在时返回`None`
+    pub fn read(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        let file = self.lock().get(path.as_ref())?.clone();
+        let data = lock(&file).data.clone();
+        Some(data)
+    }
+
+    /// 所有文件的路径，按路径排序
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = self.lock().keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// `path`被成功打开(包括创建)的次数，可以用来检查文件句柄是否被缓存
+    pub fn opens(&self, path: impl AsRef<Path>) -> usize {
+        let opens = self.opens.lock().unwrap_or_else(PoisonError::into_inner);
+        opens.get(path.as_ref()).copied().unwrap_or(0)
+    }
+
+    /// 之后打开(包括覆盖写入时改名为)`path`都返回`kind`错误，`None`表示恢复正常。
+    /// 已经打开的文件不受影响
+    ///
+    /// ```
+    /// # use std::io;
+    /// # use write_local::{MemorySink, RetryPolicy, WriteData, WriteLocal};
+    /// let sink = MemorySink::default();
+    /// let local = WriteLocal::builder()
+    ///     .sink(sink.clone())
+    ///     .retry_policy(RetryPolicy::never())
+    ///     .build()
+    ///     .unwrap();
+    /// sink.fail_open("app.log", Some(io::ErrorKind::PermissionDenied));
+    /// let ack = local.write_with_ack("app.log".into(), WriteData::Append(b"hello".to_vec().into()));
+    /// local.flush();
+    /// assert!(ack.wait().is_err());
+    /// ```
+    pub fn fail_open(&self, path: impl Into<PathBuf>, kind: Option<io::ErrorKind>) {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        match kind {
+            Some(kind) => failures.insert(path.into(), kind),
+            None => failures.remove(&path.into()),
+        };
+    }
+
+    fn check_failure(&self, path: &Path) -> io::Result<()> {
+        let failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        match failures.get(path) {
+            Some(&kind) => Err(io::Error::new(
+                kind,
+                format!("injected failure for {:?}", path.as_os_str()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, Arc<Mutex<MemoryFile>>>> {
+        self.files.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self, path: &Path) -> io::Result<Arc<Mutex<MemoryFile>>> {
+        self.lock()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| not_found(path))
+    }
+}
+
+fn lock(file: &Mutex<MemoryFile>) -> std::sync::MutexGuard<'_, MemoryFile> {
+    file.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{:?} not found in memory sink", path.as_os_str()),
+    )
+}
+
+impl Sink for MemorySink {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        self.check_failure(path)?;
+        *self
+            .opens
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(path.to_path_buf())
+            .or_default() += 1;
+        let file = self
+            .lock()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| Arc::new(Mutex::new(MemoryFile::new())))
+            .clone();
+        Ok(Box::new(MemoryHandle(file)))
+    }
+
+    fn open_positional(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        self.open(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        let file = self.open(path)?;
+        if let Some(file) = self.lock().get(path) {
+            let mut file = lock(file);
+            file.data.clear();
+            file.modified = SystemTime::now();
+        }
+        Ok(file)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_failure(to)?;
+        let mut files = self.lock();
+        let file = files.remove(from).ok_or_else(|| not_found(from))?;
+        files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.lock()
+            .remove(path)
+            .map(drop)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<SinkMetadata> {
+        let file = self.get(path)?;
+        let meta = lock(&file).metadata();
+        Ok(meta)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = match dir {
+            dir if dir == Path::new(".") => Path::new(""),
+            dir => dir,
+        };
+        Ok(self
+            .lock()
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn sync_dir(&self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SinkFile for MemoryHandle {
+    fn append(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut file = lock(&self.0);
+        file.data.extend_from_slice(data);
+        file.modified = SystemTime::now();
+        Ok(data.len())
+    }
+
+    fn append_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut file = lock(&self.0);
+        for buf in bufs {
+            file.data.extend_from_slice(buf);
+        }
+        file.modified = SystemTime::now();
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<usize> {
+        let mut file = lock(&self.0);
+        let start = usize::try_from(offset).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let end = start + data.len();
+        // 写在文件末尾之后时，中间的空洞填0
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[start..end].copy_from_slice(data);
+        file.modified = SystemTime::now();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self, _data_only: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn metadata(&self) -> io::Result<SinkMetadata> {
+        Ok(lock(&self.0).metadata())
+    }
+}
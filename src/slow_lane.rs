@@ -0,0 +1,69 @@
+use crate::{
+    ack::AckSender,
+    compression::Compression,
+    merged::Merged,
+    stats::Counters,
+    writer::{write_to_local, Config},
+    Command, FlushSummary,
+};
+use flume::Sender;
+use std::{sync::Arc, thread::JoinHandle};
+use tracing::error;
+
+/// 隔离慢速文件的后台写线程，由被拖慢的后台写线程在第一次隔离文件时启动
+///
+/// 被隔离的文件此后的所有消息都由原来的线程按收到的顺序转发过来，因此同一个文件的数据仍然按顺序写入。
+/// 慢速通道自身不再隔离文件
+pub(crate) struct SlowLane {
+    pub(crate) tx: Sender<Command>,
+    handle: JoinHandle<FlushSummary>,
+}
+
+/// 被隔离的文件尚未写入的数据，连同等待写入结果的调用者一起交给慢速通道。
+/// 暂缓合并的数据也以这种形式排队
+pub(crate) struct Transferred {
+    pub(crate) data: Merged,
+    pub(crate) acks: Vec<AckSender>,
+    /// 已合并进来的批次数
+    pub(crate) batches: usize,
+    /// data开头已经压缩过的字节数
+    pub(crate) compressed: usize,
+    /// data开头已压缩部分的压缩方式
+    pub(crate) codec: Compression,
+}
+
+impl SlowLane {
+    /// 启动第`worker`个后台写线程的慢速通道，使用相同的配置，只是不再有写入超时
+    pub(crate) fn spawn(config: &Config, counters: &Arc<Counters>, worker: usize) -> Option<Self> {
+        let mut config = config.clone();
+        config.slow_write_timeout = None;
+        let counters = counters.clone();
+        let slot = counters.slow_lane(worker);
+        // 慢速通道的channel不限容量，转发消息时不能阻塞原来的线程
+        let (tx, rx) = flume::unbounded();
+
+        let name = match std::thread::current().name() {
+            Some(name) => format!("{name}-slow"),
+            None => "write_local-slow".to_string(),
+        };
+        let res = std::thread::Builder::new()
+            .name(name)
+            .spawn(move || write_to_local(rx, config, counters, slot));
+        match res {
+            Ok(handle) => Some(Self { tx, handle }),
+            Err(e) => {
+                error!("failed to spawn write local slow lane thread: {e}");
+                None
+            }
+        }
+    }
+
+    /// 写完慢速通道中的所有数据后关闭它，返回最后一轮写入的汇总结果
+    pub(crate) fn close(self) -> FlushSummary {
+        let _ = self.tx.send(Command::Shutdown(None));
+        self.handle.join().unwrap_or_else(|_| {
+            error!("write local slow lane thread panicked");
+            FlushSummary::default()
+        })
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{
+    compression::Compression, merged::Merged, slow_lane::Transferred, Command, FsSink, Sink,
+    WriteAck, WriteData, WriteError, WriteLocal,
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+/// 同一进程内溢出文件的序号，避免同一毫秒内的溢出文件重名
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 将无法写入目标文件的数据写到溢出目录`dir`中，返回溢出数据文件的路径
+///
+/// 每份数据对应两个文件：`<名称>.data`保存数据本身，`<名称>.meta`记录写入方式(随机写入时还有偏移量)、
+/// 数据已压缩时的压缩方式、溢出时间、溢出原因和原始路径。
+/// 两个文件都持久化之后，`.meta`文件才由临时文件改名得到，只有它存在时才表示这份溢出数据是完整的
+pub(crate) fn spill(
+    dir: &Path,
+    dest_file: &Path,
+    data: &WriteData,
+    compressed: Compression,
+    error: &dyn std::error::Error,
+) -> io::Result<PathBuf> {
+    fs_err::create_dir_all(dir)?;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name = spill_name(millis, SEQ.fetch_add(1, Ordering::Relaxed));
+
+    let data_file = dir.join(format!("{name}.data"));
+    let mut file = fs_err::File::create(&data_file)?;
+    for chunk in data.payload().chunks() {
+        io::Write::write_all(&mut file, chunk)?;
+    }
+    file.sync_all()?;
+
+    let mode = match data {
+        WriteData::Append(_) => "append".to_string(),
+        WriteData::Override(_) => "override".to_string(),
+        WriteData::WriteAt { offset, .. } => format!("write_at\noffset={offset}"),
+    };
+    // 错误原因连同其底层原因写成一行
+    let mut reason = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        reason = format!("{reason}: {e}");
+        source = e.source();
+    }
+    let reason = reason.replace('\n', " ");
+    let mut header = format!("mode={mode}\n");
+    if !compressed.is_none() {
+        header += &format!("compressed={}\n", compressed.name());
+    }
+    // 原始路径放在最后一行，其内容可以是任意字节
+    let mut meta = format!("{header}time={millis}\nerror={reason}\npath=").into_bytes();
+    meta.extend(path_to_bytes(dest_file));
+    let meta_tmp = dir.join(format!("{name}.meta.tmp"));
+    let mut file = fs_err::File::create(&meta_tmp)?;
+    io::Write::write_all(&mut file, &meta)?;
+    file.sync_all()?;
+    fs_err::rename(&meta_tmp, dir.join(format!("{name}.meta")))?;
+    FsSink.sync_dir(dir)?;
+
+    Ok(data_file)
+}
+
+/// 溢出文件的名称(不含扩展名)。时间和序号补零到相同长度，按名称排序即为溢出的先后顺序
+fn spill_name(millis: u128, seq: u64) -> String {
+    format!("{millis:020}-{seq:020}-{}", std::process::id())
+}
+
+/// 溢出目录中的一份数据
+struct SpillEntry {
+    meta_file: PathBuf,
+    data_file: PathBuf,
+    dest_file: PathBuf,
+    data: WriteData,
+    /// 数据已经压缩过时的压缩方式，重新写入时不再压缩
+    compressed: Compression,
+}
+
+impl SpillEntry {
+    fn read(meta_file: PathBuf) -> io::Result<Self> {
+        let meta = fs_err::read(&meta_file)?;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid spill meta file {:?}", meta_file.as_os_str()),
+            )
+        };
+
+        let path_at = meta
+            .windows(6)
+            .position(|w| w == b"\npath=")
+            .ok_or_else(invalid)?;
+        let dest_file = path_from_bytes(&meta[path_at + 6..]);
+        let header = String::from_utf8_lossy(&meta[..path_at]);
+        let field = |name: &str| {
+            header
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+                .ok_or_else(invalid)
+        };
+        let mode = field("mode")?;
+        let compressed = match field("compressed") {
+            Ok(name) => Compression::from_name(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "spill file {:?} is compressed with {name}, which is not enabled",
+                        meta_file.as_os_str()
+                    ),
+                )
+            })?,
+            Err(_) => Compression::None,
+        };
+
+        let data_file = meta_file.with_extension("data");
+        let bytes = fs_err::read(&data_file)?;
+        let data = match mode {
+            "append" => WriteData::Append(bytes.into()),
+            "override" => WriteData::Override(bytes.into()),
+            "write_at" => WriteData::WriteAt {
+                offset: field("offset")?.parse().map_err(|_| invalid())?,
+                data: bytes.into(),
+            },
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self {
+            meta_file,
+            data_file,
+            dest_file,
+            data,
+            compressed,
+        })
+    }
+}
+
+/// [`WriteLocal::replay_spill`]的结果
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// 成功写入目标文件(或再次被溢出)并已从溢出目录中删除的份数
+    pub replayed: usize,
+    /// 仍未能写入的溢出文件及失败原因，它们保留在溢出目录中
+    pub failed: Vec<(PathBuf, WriteError)>,
+}
+
+impl WriteLocal {
+    /// 将溢出目录([`Builder::spill_dir`](crate::Builder::spill_dir)以及
+    /// [`OverflowPolicy::Spill`](crate::OverflowPolicy::Spill))中的数据按溢出的先后顺序重新写入目标文件，
+    /// 写入成功后删除对应的溢出文件
+    ///
+    /// 阻塞直到所有溢出数据都有了写入结果
+    pub fn replay_spill(&self) -> io::Result<ReplayReport> {
+        let mut report = ReplayReport::default();
+        let mut dirs = self.inner.spill_dirs.clone();
+        dirs.dedup();
+        for dir in dirs {
+            self.replay_spill_dir(&dir, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    fn replay_spill_dir(&self, dir: &Path, report: &mut ReplayReport) -> io::Result<()> {
+        let mut metas = Vec::new();
+        match fs_err::read_dir(dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let path = entry?.path();
+                    if path.extension().is_some_and(|ext| ext == "meta") {
+                        metas.push(path);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        // 文件名以补零的溢出时间和序号开头，排序后即为溢出的先后顺序
+        metas.sort();
+
+        let mut acks = Vec::with_capacity(metas.len());
+        for meta_file in metas {
+            match SpillEntry::read(meta_file.clone()) {
+                Ok(entry) => {
+                    let ack = self.replay_entry(entry.dest_file, entry.data, entry.compressed);
+                    acks.push((entry.meta_file, entry.data_file, ack));
+                }
+                Err(e) => warn!("skip spill file: {e}"),
+            }
+        }
+        self.flush();
+
+        for (meta_file, data_file, ack) in acks {
+            match ack.wait() {
+                // 再次溢出时已经有了新的溢出文件，旧的可以删除
+                Ok(_) | Err(WriteError::Spilled { .. }) => {
+                    fs_err::remove_file(meta_file)?;
+                    fs_err::remove_file(data_file)?;
+                    report.replayed += 1;
+                }
+                Err(e) => report.failed.push((data_file, e)),
+            }
+        }
+        info!("replayed {} spilled data from {:?}", report.replayed, dir);
+        Ok(())
+    }
+
+    /// 重新写入一份溢出数据。已经压缩过的数据标记为已压缩，不会按目标文件的压缩设置再压缩一次。
+    /// 重新写入时本就要等待写入结果，因此不按[`OverflowPolicy`](crate::OverflowPolicy)处理，
+    /// channel已满时总是阻塞等待，发送失败时ack随之关闭
+    fn replay_entry(&self, dest_file: PathBuf, data: WriteData, codec: Compression) -> WriteAck {
+        let (ack_tx, ack) = WriteAck::new();
+        let cmd = if codec.is_none() {
+            Command::Write(dest_file, data, Some(ack_tx))
+        } else {
+            let compressed = data.len();
+            let transferred = Transferred {
+                data: Merged::from(data),
+                acks: vec![ack_tx],
+                batches: 1,
+                compressed,
+                codec,
+            };
+            Command::Transfer(dest_file, Box::new(transferred))
+        };
+        let inner = &self.inner;
+        let _ = inner.send(inner.route(&cmd), cmd);
+        ack
+    }
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spill_names_sort_by_time_then_seq() {
+        let names = [
+            spill_name(999, 10),
+            spill_name(1000, 0),
+            spill_name(1000, 9),
+            spill_name(1000, 10),
+            spill_name(1000, 100),
+        ];
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(sorted, names);
+    }
+}
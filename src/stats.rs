@@ -0,0 +1,402 @@
+use crate::{OverflowCounts, OverflowPolicy};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+/// 后台写线程开始一轮写入的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushTrigger {
+    /// 收到第一条消息后等待的时间已到
+    Deadline,
+    /// 收集到的数据总字节数达到上限
+    BatchBytes,
+    /// 某个文件待写入的字节数达到上限
+    FileBytes,
+    /// 收集到的消息条数达到上限
+    BatchCount,
+    /// 有人调用了flush或shutdown
+    Barrier,
+    /// 重试之前写入失败的数据
+    Retry,
+    /// 所有待写数据的字节数达到上限
+    MemoryBudget,
+}
+
+/// 各种原因触发写入的次数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TriggerCounts {
+    pub deadline: u64,
+    pub batch_bytes: u64,
+    pub file_bytes: u64,
+    pub batch_count: u64,
+    pub barrier: u64,
+    pub retry: u64,
+    pub memory_budget: u64,
+}
+
+/// 后台写线程中缓存的待写数据占用的内存情况
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// 缓存了多少个文件
+    pub cached_paths: u64,
+    /// 尚未写入的数据的字节数，包括写入失败等待重试的数据
+    pub pending_bytes: u64,
+    /// 所有缓冲区占用的字节数，不包括与调用者共享的数据
+    pub buffer_capacity: u64,
+}
+
+/// 写入单个文件的耗时，包括打开文件和持久化
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// 写入了多少次，包括失败的
+    pub count: u64,
+    /// 总耗时
+    pub total: Duration,
+    /// 最长的一次耗时
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// 平均耗时
+    pub fn mean(&self) -> Duration {
+        match u32::try_from(self.count) {
+            Ok(0) => Duration::ZERO,
+            Ok(count) => self.total / count,
+            Err(_) => Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64),
+        }
+    }
+}
+
+/// 单个文件的写入情况
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathStats {
+    /// 写入成功的次数
+    pub writes: u64,
+    /// 写入磁盘的字节数，压缩时为压缩后的字节数
+    pub bytes_written: u64,
+    /// 写入失败的次数，包括之后重试成功的
+    pub errors: u64,
+}
+
+/// [`WriteLocal::stats`](crate::WriteLocal::stats)返回的统计信息快照
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// channel中尚未被后台写线程取走的消息条数
+    pub queued: usize,
+    /// 后台写线程收到的写入消息条数
+    pub messages: u64,
+    /// 合并到同一文件尚未写入的数据中的消息条数
+    pub merged: u64,
+    /// 写入的轮数
+    pub batches: u64,
+    /// 写入磁盘的总字节数
+    pub bytes_written: u64,
+    /// 写入失败的次数，包括之后重试成功的
+    pub write_errors: u64,
+    /// 重试后仍未能写入而放弃的次数
+    pub given_up: u64,
+    /// 因写入太慢被隔离到慢速通道的文件数
+    pub quarantined: u64,
+    /// 写入单个文件的耗时
+    pub latency: LatencyStats,
+    /// 后台写线程中缓存的各个文件的写入情况，文件闲置超时被移出缓存后不再统计
+    pub paths: HashMap<PathBuf, PathStats>,
+    pub triggers: TriggerCounts,
+    pub overflow: OverflowCounts,
+    pub memory: MemoryStats,
+}
+
+/// 后台写线程与[`WriteLocal`](crate::WriteLocal)共享的计数器
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    deadline: AtomicU64,
+    batch_bytes: AtomicU64,
+    file_bytes: AtomicU64,
+    batch_count: AtomicU64,
+    barrier: AtomicU64,
+    retry: AtomicU64,
+    memory_budget: AtomicU64,
+    dropped_newest: AtomicU64,
+    dropped_oldest: AtomicU64,
+    spilled: AtomicU64,
+    messages: AtomicU64,
+    merged: AtomicU64,
+    bytes_written: AtomicU64,
+    write_errors: AtomicU64,
+    given_up: AtomicU64,
+    quarantined: AtomicU64,
+    latency_count: AtomicU64,
+    latency_nanos: AtomicU64,
+    latency_max_nanos: AtomicU64,
+    /// 各个后台写线程各自的计数器，下标为线程的序号
+    workers: Vec<WorkerCounters>,
+}
+
+/// 只由一个后台写线程更新的计数器，读取时汇总所有线程的
+#[derive(Debug, Default)]
+struct WorkerCounters {
+    cached_paths: AtomicU64,
+    pending_bytes: AtomicU64,
+    buffer_capacity: AtomicU64,
+    paths: Mutex<HashMap<PathBuf, PathStats>>,
+}
+
+impl Counters {
+    /// 每个后台写线程及其慢速通道各有一份计数器
+    pub(crate) fn new(workers: usize) -> Self {
+        Self {
+            workers: (0..workers * 2)
+                .map(|_| WorkerCounters::default())
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// 第`worker`个后台写线程的慢速通道使用的计数器的序号
+    pub(crate) fn slow_lane(&self, worker: usize) -> usize {
+        self.workers.len() / 2 + worker
+    }
+
+    /// 序号为`worker`的计数器是否属于慢速通道
+    pub(crate) fn is_slow_lane(&self, worker: usize) -> bool {
+        worker >= self.workers.len() / 2
+    }
+
+    pub(crate) fn triggered(&self, trigger: FlushTrigger) {
+        let counter = match trigger {
+            FlushTrigger::Deadline => &self.deadline,
+            FlushTrigger::BatchBytes => &self.batch_bytes,
+            FlushTrigger::FileBytes => &self.file_bytes,
+            FlushTrigger::BatchCount => &self.batch_count,
+            FlushTrigger::Barrier => &self.barrier,
+            FlushTrigger::Retry => &self.retry,
+            FlushTrigger::MemoryBudget => &self.memory_budget,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("write_local_batches_total", "trigger" => format!("{trigger:?}"))
+            .increment(1);
+    }
+
+    /// channel已满时，按`policy`处理了一条数据
+    pub(crate) fn overflowed(&self, policy: &OverflowPolicy) {
+        let (counter, name) = match policy {
+            OverflowPolicy::Block => return,
+            OverflowPolicy::DropNewest => (&self.dropped_newest, "drop_newest"),
+            OverflowPolicy::DropOldest => (&self.dropped_oldest, "drop_oldest"),
+            OverflowPolicy::Spill(_) => (&self.spilled, "spill"),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("write_local_overflow_total", "policy" => name).increment(1);
+        #[cfg(not(feature = "metrics"))]
+        let _ = name;
+    }
+
+    /// 收到一条写入消息，`merged`表示是否合并到了尚未写入的数据中
+    pub(crate) fn accepted(&self, merged: bool) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        if merged {
+            self.merged.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!("write_local_messages_total").increment(1);
+            if merged {
+                metrics::counter!("write_local_merged_total").increment(1);
+            }
+        }
+    }
+
+    /// 写入了一次`path`，成功时`bytes`为写入的字节数
+    pub(crate) fn wrote(
+        &self,
+        worker: usize,
+        path: &Path,
+        bytes: Option<usize>,
+        elapsed: Duration,
+    ) {
+        let nanos = elapsed.as_nanos() as u64;
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.latency_max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        match bytes {
+            Some(bytes) => self
+                .bytes_written
+                .fetch_add(bytes as u64, Ordering::Relaxed),
+            None => self.write_errors.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let mut paths = self.workers[worker].paths();
+        let stats = match paths.get_mut(path) {
+            Some(stats) => stats,
+            None => paths.entry(path.to_path_buf()).or_default(),
+        };
+        match bytes {
+            Some(bytes) => {
+                stats.writes += 1;
+                stats.bytes_written += bytes as u64;
+            }
+            None => stats.errors += 1,
+        }
+        drop(paths);
+
+        // 文件数量没有上限，不作为metrics的标签，各个文件的写入情况见`Stats::paths`
+        #[cfg(feature = "metrics")]
+        {
+            metrics::histogram!("write_local_write_seconds").record(elapsed.as_secs_f64());
+            match bytes {
+                Some(bytes) => {
+                    metrics::counter!("write_local_bytes_written_total").increment(bytes as u64)
+                }
+                None => metrics::counter!("write_local_write_errors_total").increment(1),
+            }
+        }
+    }
+
+    /// 放弃写入了一次数据
+    pub(crate) fn gave_up(&self) {
+        self.given_up.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("write_local_given_up_total").increment(1);
+    }
+
+    /// 隔离了一个文件到慢速通道
+    pub(crate) fn quarantined(&self) {
+        self.quarantined.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("write_local_quarantined_total").increment(1);
+    }
+
+    /// 只保留仍在后台写线程中缓存的文件的统计
+    pub(crate) fn retain_paths(&self, worker: usize, mut keep: impl FnMut(&Path) -> bool) {
+        let mut paths = self.workers[worker].paths();
+        paths.retain(|path, _| keep(path));
+    }
+
+    pub(crate) fn stats(&self, queued: usize) -> Stats {
+        let triggers = self.trigger_counts();
+        let TriggerCounts {
+            deadline,
+            batch_bytes,
+            file_bytes,
+            batch_count,
+            barrier,
+            retry,
+            memory_budget,
+        } = triggers;
+        let mut paths = HashMap::new();
+        for worker in &self.workers {
+            paths.extend(
+                worker
+                    .paths()
+                    .iter()
+                    .map(|(path, stats)| (path.clone(), *stats)),
+            );
+        }
+        Stats {
+            queued,
+            messages: self.messages.load(Ordering::Relaxed),
+            merged: self.merged.load(Ordering::Relaxed),
+            batches: deadline
+                + batch_bytes
+                + file_bytes
+                + batch_count
+                + barrier
+                + retry
+                + memory_budget,
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            given_up: self.given_up.load(Ordering::Relaxed),
+            quarantined: self.quarantined.load(Ordering::Relaxed),
+            latency: LatencyStats {
+                count: self.latency_count.load(Ordering::Relaxed),
+                total: Duration::from_nanos(self.latency_nanos.load(Ordering::Relaxed)),
+                max: Duration::from_nanos(self.latency_max_nanos.load(Ordering::Relaxed)),
+            },
+            paths,
+            triggers,
+            overflow: self.overflow_counts(),
+            memory: self.memory_stats(),
+        }
+    }
+
+    pub(crate) fn trigger_counts(&self) -> TriggerCounts {
+        TriggerCounts {
+            deadline: self.deadline.load(Ordering::Relaxed),
+            batch_bytes: self.batch_bytes.load(Ordering::Relaxed),
+            file_bytes: self.file_bytes.load(Ordering::Relaxed),
+            batch_count: self.batch_count.load(Ordering::Relaxed),
+            barrier: self.barrier.load(Ordering::Relaxed),
+            retry: self.retry.load(Ordering::Relaxed),
+            memory_budget: self.memory_budget.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn overflow_counts(&self) -> OverflowCounts {
+        OverflowCounts {
+            dropped_newest: self.dropped_newest.load(Ordering::Relaxed),
+            dropped_oldest: self.dropped_oldest.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn set_pending_bytes(&self, worker: usize, pending_bytes: usize) {
+        self.workers[worker]
+            .pending_bytes
+            .store(pending_bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_memory(
+        &self,
+        worker: usize,
+        cached_paths: usize,
+        pending_bytes: usize,
+        capacity: usize,
+    ) {
+        let counters = &self.workers[worker];
+        counters
+            .cached_paths
+            .store(cached_paths as u64, Ordering::Relaxed);
+        self.set_pending_bytes(worker, pending_bytes);
+        counters
+            .buffer_capacity
+            .store(capacity as u64, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        {
+            let worker = worker.to_string();
+            metrics::gauge!("write_local_cached_paths", "worker" => worker.clone())
+                .set(cached_paths as f64);
+            metrics::gauge!("write_local_pending_bytes", "worker" => worker.clone())
+                .set(pending_bytes as f64);
+            metrics::gauge!("write_local_buffer_capacity_bytes", "worker" => worker)
+                .set(capacity as f64);
+        }
+    }
+
+    pub(crate) fn memory_stats(&self) -> MemoryStats {
+        let sum = |counter: fn(&WorkerCounters) -> &AtomicU64| {
+            self.workers
+                .iter()
+                .map(|worker| counter(worker).load(Ordering::Relaxed))
+                .sum()
+        };
+        MemoryStats {
+            cached_paths: sum(|worker| &worker.cached_paths),
+            pending_bytes: sum(|worker| &worker.pending_bytes),
+            buffer_capacity: sum(|worker| &worker.buffer_capacity),
+        }
+    }
+}
+
+impl WorkerCounters {
+    fn paths(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, PathStats>> {
+        self.paths.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,113 @@
+//! 测试辅助工具，需要启用`test-util` feature
+//!
+//! [`TestLocal`]将数据写入内存中的[`MemorySink`]，并使用手动推进的时钟，
+//! 收集数据的等待时间、句柄闲置超时、重试等待等都只在调用[`TestLocal::advance`]时才会到期，
+//! 测试结果不受机器快慢的影响。通过[`TestLocal::sink`]的[`MemorySink::fail_open`]可以模拟写入失败
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use write_local::{test_util::TestLocal, WriteData};
+//! let local = TestLocal::new();
+//! local.write("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+//! local.write("a.log".into(), WriteData::Append(b"2".to_vec().into()));
+//! // 等待时间未到，数据还没有写入
+//! local.advance(Duration::from_millis(99));
+//! assert_eq!(local.read("a.log"), None);
+//! // 等待时间已到，合并后一次写入
+//! local.advance(Duration::from_millis(1));
+//! assert_eq!(local.read("a.log").unwrap(), b"12");
+//! ```
+
+use crate::{clock::Clock, Builder, Command, FlushSummary, MemorySink, WriteLocal};
+use flume::bounded;
+use std::{
+    ops::Deref,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// 写入内存、使用手动推进的时钟的[`WriteLocal`]，可以直接调用`WriteLocal`的所有方法
+pub struct TestLocal {
+    local: WriteLocal,
+    sink: MemorySink,
+    clock: Clock,
+}
+
+impl TestLocal {
+    /// 使用默认配置
+    pub fn new() -> Self {
+        Self::with_builder(WriteLocal::builder())
+    }
+
+    /// 使用`builder`中的配置，其中的[`Builder::sink`]会被替换为[`MemorySink`]
+    pub fn with_builder(builder: Builder) -> Self {
+        let sink = MemorySink::default();
+        let clock = Clock::manual();
+        let local = builder
+            .sink(sink.clone())
+            .clock(clock.clone())
+            .build()
+            .expect("failed to spawn write local thread");
+        Self { local, sink, clock }
+    }
+
+    /// 写入的数据
+    pub fn sink(&self) -> &MemorySink {
+        &self.sink
+    }
+
+    /// `path`的内容，不存在时返回`None`
+    pub fn read(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.sink.read(path)
+    }
+
+    /// 将时钟推进`duration`，并等待后台写线程处理完所有到期的事件，
+    /// 包括在此之前发送的数据的收集等待、句柄闲置超时和写入失败后的重试
+    pub fn advance(&self, duration: Duration) {
+        // 先让后台写线程在推进前的时间处理完已发送的消息，收集等待的截止时间才不会被推迟
+        self.tick();
+        self.clock.advance(duration);
+        self.tick();
+    }
+
+    fn tick(&self) {
+        let inner = &self.local.inner;
+        let pending: Vec<_> = inner
+            .workers
+            .iter()
+            .filter_map(|worker| {
+                let (done, rx) = bounded(1);
+                inner.send(worker, Command::Tick(done)).ok()?;
+                Some(rx)
+            })
+            .collect();
+        for rx in pending {
+            let _ = rx.recv();
+        }
+    }
+
+    /// 将日期时间设置为`time`，之后随[`TestLocal::advance`]一同推进。
+    /// 文件按时间轮转时使用这个时间，默认为创建`TestLocal`时的系统时间
+    pub fn set_wall_clock(&self, time: SystemTime) {
+        self.clock.set_wall(time);
+    }
+
+    /// 不等待时间到期，立即写入所有已收到的数据，同[`WriteLocal::flush`]
+    pub fn run_batch(&self) -> Option<FlushSummary> {
+        self.local.flush()
+    }
+}
+
+impl Default for TestLocal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for TestLocal {
+    type Target = WriteLocal;
+
+    fn deref(&self) -> &WriteLocal {
+        &self.local
+    }
+}
@@ -0,0 +1,264 @@
+use crate::sink::{append_one, BatchAppend, FsFile, FsSink, Sink, SinkFile, SinkMetadata};
+use io_uring::{opcode, squeue, types, IoUring};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    io,
+    os::unix::{ffi::OsStrExt, io::FromRawFd},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+use tracing::error;
+
+/// 通过io_uring提交每轮写入的[`Sink`]，仅支持Linux，需要启用`io-uring`特性
+///
+/// 每轮写入中尚未缓存句柄的文件一起通过io_uring打开(openat)，之后所有文件的追加写入(writev)
+/// 及其后的fsync再一起提交，句柄缓存命中时只需一次提交。每个文件的写入与其fsync链接在一起，
+/// 写入出错或只写入了一部分时不执行fsync，由后台写线程写完剩下的数据后再同步
+///
+/// 覆盖写入、随机写入以及改名等其他操作与[`FsSink`]相同，逐个文件通过普通的系统调用完成
+///
+/// io_uring提交失败后不再使用它，之后的写入与[`FsSink`]相同。已提交的操作总是等到全部完成后才返回
+///
+/// ```no_run
+/// # use write_local::{UringSink, WriteLocal};
+/// let local = WriteLocal::builder()
+///     .sink(UringSink::new(256).unwrap())
+///     .build()
+///     .unwrap();
+/// ```
+pub struct UringSink {
+    /// 提交失败后io_uring不再可用，置为`None`
+    ring: Mutex<Option<IoUring>>,
+    fs: FsSink,
+}
+
+impl UringSink {
+    /// 创建最多同时提交`entries`个操作的io_uring，一轮写入的操作更多时分多次提交。
+    /// 内核不支持或禁止使用io_uring时返回错误
+    pub fn new(entries: u32) -> io::Result<Self> {
+        Ok(Self {
+            ring: Mutex::new(Some(IoUring::new(entries.max(2))?)),
+            fs: FsSink,
+        })
+    }
+}
+
+impl Sink for UringSink {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        self.fs.open(path)
+    }
+
+    fn open_positional(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        self.fs.open_positional(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        self.fs.create(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.fs.rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.fs.remove(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<SinkMetadata> {
+        self.fs.metadata(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.fs.read_dir(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.fs.sync_dir(dir)
+    }
+
+    fn copy_attributes(
+        &self,
+        from: &Path,
+        to: &Path,
+        permissions: bool,
+        owner: bool,
+    ) -> io::Result<()> {
+        self.fs.copy_attributes(from, to, permissions, owner)
+    }
+
+    fn open_batch(&self, paths: &[&Path]) -> Vec<io::Result<Box<dyn SinkFile>>> {
+        // 与FsSink::open相同的方式打开：追加写入，不存在时创建
+        let c_paths: Vec<_> = paths
+            .iter()
+            .map(|path| CString::new(path.as_os_str().as_bytes()))
+            .collect();
+        let flags = libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT | libc::O_CLOEXEC;
+        let groups = c_paths
+            .iter()
+            .enumerate()
+            .filter_map(|(i, path)| {
+                let path = path.as_ref().ok()?;
+                let open = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), path.as_ptr())
+                    .flags(flags)
+                    .mode(0o666)
+                    .build()
+                    .user_data(i as u64);
+                Some(vec![open])
+            })
+            .collect();
+        // 提交的操作全部完成之前不会返回，期间c_paths一直有效
+        let mut results = self.submit(groups);
+        paths
+            .iter()
+            .zip(c_paths)
+            .enumerate()
+            .map(|(i, (path, c_path))| {
+                c_path.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                match results.remove(&(i as u64)) {
+                    // io_uring不可用时逐个打开
+                    None => self.fs.open(path),
+                    Some(Err(e)) => Err(e),
+                    Some(Ok(fd)) if fd < 0 => {
+                        let e = io::Error::from_raw_os_error(-fd);
+                        Err(io::Error::new(
+                            e.kind(),
+                            format!("failed to open file `{}`: {e}", path.display()),
+                        ))
+                    }
+                    Some(Ok(fd)) => {
+                        let file = unsafe { std::fs::File::from_raw_fd(fd) };
+                        let file: Box<dyn SinkFile> =
+                            Box::new(FsFile(fs_err::File::from_parts(file, path)));
+                        Ok(file)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn append_batch(&self, batch: &mut [BatchAppend<'_>]) {
+        // 每个文件的操作：writev，需要同步时再链接一个fsync。user_data的最低位区分二者
+        let mut groups = Vec::with_capacity(batch.len());
+        for (i, append) in batch.iter_mut().enumerate() {
+            let Some(fd) = append.file.as_raw_fd() else {
+                append.result = append_one(append);
+                continue;
+            };
+            let fd = types::Fd(fd);
+            // IoSlice在unix上与iovec的内存布局相同；offset为-1表示从当前位置写入，追加打开的文件总是写在末尾
+            let write =
+                opcode::Writev::new(fd, append.bufs.as_ptr().cast(), append.bufs.len() as u32)
+                    .offset(u64::MAX)
+                    .build()
+                    .user_data((i as u64) << 1);
+            let group = match append.sync {
+                None => vec![write],
+                Some(data_only) => {
+                    let flags = match data_only {
+                        true => types::FsyncFlags::DATASYNC,
+                        false => types::FsyncFlags::empty(),
+                    };
+                    let sync = opcode::Fsync::new(fd)
+                        .flags(flags)
+                        .build()
+                        .user_data(((i as u64) << 1) | 1);
+                    vec![write.flags(squeue::Flags::IO_LINK), sync]
+                }
+            };
+            groups.push(group);
+        }
+
+        // 提交的操作全部完成之前不会返回，期间batch中的数据一直有效
+        let mut results = self.submit(groups);
+        for (i, append) in batch.iter_mut().enumerate() {
+            if append.file.as_raw_fd().is_none() {
+                continue;
+            }
+            let write = (i as u64) << 1;
+            match results.remove(&write) {
+                // io_uring不可用时逐个写入
+                None => {
+                    append.result = append_one(append);
+                    continue;
+                }
+                Some(Ok(n)) if n >= 0 => append.written = n as usize,
+                Some(Ok(n)) => append.result = Err(io::Error::from_raw_os_error(-n)),
+                Some(Err(e)) => append.result = Err(e),
+            }
+            match results.remove(&(write | 1)) {
+                None | Some(Ok(0..)) => {}
+                // 写入出错或只写入了一部分时，链接的fsync被取消
+                Some(Ok(n)) if -n == libc::ECANCELED => {}
+                Some(Ok(n)) => append.result = Err(io::Error::from_raw_os_error(-n)),
+                Some(Err(e)) => append.result = Err(e),
+            }
+        }
+    }
+}
+
+impl UringSink {
+    /// 分批提交各组操作，同一组的操作总在同一次提交中，等待全部完成后按user_data返回各个操作的结果：
+    /// 执行了的操作为其返回值(负数为错误码)，提交出错而没有执行的操作为该错误。
+    /// io_uring不可用时没有提交的操作不在结果中，由调用者改用普通的系统调用
+    fn submit(&self, groups: Vec<Vec<squeue::Entry>>) -> HashMap<u64, io::Result<i32>> {
+        let mut results = HashMap::new();
+        let mut guard = self.ring.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut groups = groups.into_iter().peekable();
+        while groups.peek().is_some() {
+            let Some(ring) = guard.as_mut() else {
+                break;
+            };
+            let capacity = ring.submission().capacity();
+            let mut entries = Vec::new();
+            while let Some(group) = groups.next_if(|group| entries.len() + group.len() <= capacity)
+            {
+                entries.extend(group);
+            }
+            if unsafe { ring.submission().push_multiple(&entries) }.is_err() {
+                for entry in &entries {
+                    let e = io::Error::other("io_uring submission queue is full");
+                    results.insert(entry.get_user_data(), Err(e));
+                }
+                continue;
+            }
+            let mut remaining = entries.len();
+            let mut broken = None;
+            while remaining > 0 {
+                match &broken {
+                    None => match ring.submit_and_wait(remaining) {
+                        Ok(_) => {}
+                        Err(e) if matches!(e.raw_os_error(), Some(libc::EINTR | libc::EBUSY)) => {}
+                        Err(e) => {
+                            error!("io_uring submission failed, fall back to plain syscalls: {e}");
+                            // 内核尚未取走的操作不会再执行；已取走的仍在进行，可能还在使用调用者的数据，
+                            // 必须等到它们都完成后才能返回
+                            let mut submission = ring.submission();
+                            submission.sync();
+                            remaining -= submission.len();
+                            broken = Some(e);
+                        }
+                    },
+                    // 不再提交，只等待已取走的操作完成
+                    Some(_) => thread::sleep(Duration::from_millis(1)),
+                }
+                for cqe in ring.completion() {
+                    remaining -= 1;
+                    results.insert(cqe.user_data(), Ok(cqe.result()));
+                }
+            }
+            // 未执行的操作都算失败
+            if let Some(e) = &broken {
+                for entry in &entries {
+                    results
+                        .entry(entry.get_user_data())
+                        .or_insert_with(|| Err(io::Error::new(e.kind(), e.to_string())));
+                }
+                *guard = None;
+            }
+        }
+        results
+    }
+}
@@ -0,0 +1,1296 @@
+use crate::{
+    ack::AckSender,
+    atomic::{write_atomic, Preserve},
+    clock::Clock,
+    compression::Compression,
+    durability::Durability,
+    handles::{is_open_timeout, HandleCache, OpenMode},
+    merged::Merged,
+    payload::Payload,
+    retry::{DeadLetter, DeadLetterHandler, RetryPolicy},
+    rotation::{Rotation, RotationPolicy},
+    sink::{write_all, BatchAppend, SharedSink, Sink, SinkFile},
+    slow_lane::{SlowLane, Transferred},
+    spill,
+    stats::{Counters, FlushTrigger},
+    Command, FlushSummary, WriteError, WriteReport,
+};
+use flume::{bounded, Receiver, RecvTimeoutError, Sender};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, info, warn};
+
+/// 后台写线程的配置
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// 收到第一条消息后，最多再等待多久以收集更多要合并的数据
+    pub(crate) batch_window: Duration,
+    /// 收集到的数据总字节数达到该值时，立即写入
+    pub(crate) max_batch_bytes: Option<usize>,
+    /// 某个文件待写入的数据达到该字节数时，立即写入
+    pub(crate) max_file_bytes: Option<usize>,
+    /// 收集到的消息达到该条数时，立即写入
+    pub(crate) max_batch_count: Option<usize>,
+    /// 最多缓存多少个打开的文件句柄，为0时不缓存
+    pub(crate) max_open_files: usize,
+    /// 文件句柄闲置多久后关闭
+    pub(crate) idle_file_timeout: Duration,
+    /// 是否通过临时文件+改名的方式原子地覆盖写入
+    pub(crate) atomic_override: bool,
+    /// 原子覆盖写入时，是否保留原文件的属性
+    pub(crate) preserve: Preserve,
+    /// 未单独设置的文件，写入后要确保持久化到什么程度
+    pub(crate) durability: Durability,
+    /// 写入失败时的重试策略
+    pub(crate) retry: RetryPolicy,
+    /// 处理最终未能写入的数据
+    pub(crate) dead_letter: Option<DeadLetterHandler>,
+    /// 最终未能写入的数据写入该溢出目录
+    pub(crate) spill_dir: Option<PathBuf>,
+    /// 没有待写数据的文件闲置多久后，从cached中移除
+    pub(crate) idle_path_timeout: Duration,
+    /// cached中所有待写数据的字节数上限
+    pub(crate) max_pending_bytes: Option<usize>,
+    /// 写入单个文件超过该时长时，将该文件隔离到慢速通道
+    pub(crate) slow_write_timeout: Option<Duration>,
+    /// 写入数据的存储后端
+    pub(crate) sink: SharedSink,
+    pub(crate) clock: Clock,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            batch_window: Duration::from_millis(100),
+            max_batch_bytes: None,
+            max_file_bytes: None,
+            max_batch_count: None,
+            max_open_files: 64,
+            idle_file_timeout: Duration::from_secs(30),
+            atomic_override: true,
+            preserve: Preserve {
+                permissions: true,
+                owner: false,
+            },
+            durability: Durability::None,
+            retry: RetryPolicy::default(),
+            dead_letter: None,
+            spill_dir: None,
+            idle_path_timeout: Duration::from_secs(60),
+            max_pending_bytes: None,
+            slow_write_timeout: None,
+            sink: SharedSink::default(),
+            clock: Clock::default(),
+        }
+    }
+}
+
+/// 某个文件待写入的数据
+struct Pending {
+    data: Merged,
+    /// 本轮合并进来的批次数
+    batches: usize,
+    /// 等待写入结果的调用者
+    acks: Vec<AckSender>,
+    /// 写入失败前已经写入的字节数，全部写完后才清零
+    written: usize,
+    /// 最近一次写入失败的原因
+    error: Option<Arc<io::Error>>,
+    /// 已经尝试写入了多少次
+    attempts: u32,
+    /// 写入失败后，下次重试的时间点
+    retry_at: Option<Instant>,
+    /// 最近一次收到数据或写完数据的时间点
+    last_used: Instant,
+    /// data开头已经压缩过的字节数，之后追加进来的数据尚未压缩
+    compressed: usize,
+    /// data开头已压缩部分的压缩方式
+    codec: Compression,
+}
+
+impl Pending {
+    fn new(now: Instant) -> Self {
+        Self {
+            data: Merged::Append(Payload::new()),
+            batches: 0,
+            acks: Vec::new(),
+            written: 0,
+            error: None,
+            attempts: 0,
+            retry_at: None,
+            last_used: now,
+            compressed: 0,
+            codec: Compression::None,
+        }
+    }
+}
+
+/// 针对单个文件的设置
+pub(crate) enum PathSetting {
+    Durability(Durability),
+    Rotation(Option<Box<RotationPolicy>>),
+    Compression(Compression),
+    SlowWriteTimeout(Duration),
+}
+
+/// 单个文件的设置，未设置的项使用[`Config`]中的值
+#[derive(Default)]
+struct PathOptions {
+    durability: Option<Durability>,
+    rotation: Option<Rotation>,
+    compression: Compression,
+    slow_write_timeout: Option<Duration>,
+}
+
+/// 后台写线程的状态
+struct Writer {
+    worker: usize,
+    config: Config,
+    counters: Arc<Counters>,
+    cached: HashMap<PathBuf, Pending>,
+    /// 与cached中尚未写入的数据无法合并的数据，按到达顺序排队，等前面的数据写完后再合并进来
+    deferred: HashMap<PathBuf, VecDeque<Transferred>>,
+    options: HashMap<PathBuf, PathOptions>,
+    handles: HandleCache,
+    /// 等待本轮写入完成的flush调用者
+    flushes: Vec<(Option<PathBuf>, Sender<FlushSummary>)>,
+    shutdown: bool,
+    /// 等待后台写线程退出的调用者，关闭channel后才发回汇总结果
+    shutdown_done: Option<Sender<FlushSummary>>,
+    /// 本轮收集到的数据总字节数
+    batch_bytes: usize,
+    /// 本轮收集到的消息条数
+    batch_count: usize,
+    /// cached中所有待写数据的字节数
+    pending_bytes: usize,
+    /// 有写入失败的数据时，最早的重试时间点
+    retry_at: Option<Instant>,
+    /// 等待处理完到期事件的时钟推进者
+    #[cfg(feature = "test-util")]
+    ticks: Vec<Sender<()>>,
+    /// 隔离慢速文件的线程，第一次隔离文件时才启动
+    slow_lane: Option<SlowLane>,
+    /// 已隔离到慢速通道的文件
+    quarantined: HashSet<PathBuf>,
+    /// 本轮写入中发现写得太慢，要隔离的文件
+    to_quarantine: Vec<PathBuf>,
+}
+
+/// 后台写线程，`worker`为线程的序号
+///
+/// 写入过程中panic时，丢弃此后收到的所有消息，等待flush或写入结果的调用者随即得知后台写线程已关闭，
+/// 收到退出消息后再将panic交给等待线程退出的调用者
+pub(crate) fn write_to_local(
+    rx: Receiver<Command>,
+    config: Config,
+    counters: Arc<Counters>,
+    worker: usize,
+) -> FlushSummary {
+    match panic::catch_unwind(AssertUnwindSafe(|| run(&rx, config, counters, worker))) {
+        Ok((summary, done)) => {
+            // 先关闭channel，调用者收到汇总结果后再发送的消息都会立即失败，不会留在channel中无人处理
+            drop(rx);
+            if let Some(done) = done {
+                let _ = done.send(summary.clone());
+            }
+            summary
+        }
+        Err(payload) => {
+            error!("write local thread panicked, discard all further commands");
+            for cmd in rx.iter() {
+                if let Command::Shutdown(_) = cmd {
+                    break;
+                }
+            }
+            panic::resume_unwind(payload)
+        }
+    }
+}
+
+fn run(
+    rx: &Receiver<Command>,
+    config: Config,
+    counters: Arc<Counters>,
+    worker: usize,
+) -> (FlushSummary, Option<Sender<FlushSummary>>) {
+    let mut writer = Writer {
+        worker,
+        handles: HandleCache::new(
+            config.sink.0.clone(),
+            config.clock.clone(),
+            config.max_open_files,
+            config.idle_file_timeout,
+        ),
+        config,
+        counters,
+        cached: HashMap::with_capacity(10),
+        deferred: HashMap::new(),
+        options: HashMap::new(),
+        flushes: Vec::new(),
+        shutdown: false,
+        shutdown_done: None,
+        batch_bytes: 0,
+        batch_count: 0,
+        pending_bytes: 0,
+        retry_at: None,
+        #[cfg(feature = "test-util")]
+        ticks: Vec::new(),
+        slow_lane: None,
+        quarantined: HashSet::new(),
+        to_quarantine: Vec::new(),
+    };
+
+    loop {
+        // 先阻塞等待第一条要写入的数据。有缓存的文件句柄或文件时，最多等到最早的句柄或文件闲置超时，
+        // 关闭或移除它之后再继续等；有写入失败的数据时，最多等到重试的时间点
+        let mut trigger = loop {
+            let deadline = [
+                writer.handles.idle_deadline(),
+                writer.idle_path_deadline(),
+                writer.retry_at,
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            match writer.recv(rx, deadline) {
+                #[cfg(feature = "test-util")]
+                Ok(Command::Tick(done)) => writer.tick(done),
+                Ok(cmd) => {
+                    // 设置、空数据等没有带来要写的数据时，不开始新的一轮，继续等待
+                    let batch_count = writer.batch_count;
+                    let trigger = writer.accept(cmd);
+                    if trigger.is_some() || writer.batch_count != batch_count {
+                        break trigger;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    writer.handles.close_idle();
+                    writer.evict_idle_paths();
+                    if writer
+                        .retry_at
+                        .is_some_and(|retry| retry <= writer.config.clock.now())
+                    {
+                        break Some(FlushTrigger::Retry);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    // 所有WriteLocal都已经drop，此时cached中已经没有待写的数据
+                    warn!("write local channel sender closed");
+                    return (writer.close_slow_lane(FlushSummary::default()), None);
+                }
+            }
+        };
+
+        // 然后等待一会会，收集更多要合并的数据，直到超时或者收集到的数据已经足够多。
+        // 要退出或有人在等待写入完成时则不再等待
+        let deadline = writer.config.clock.now() + writer.config.batch_window;
+        while trigger.is_none() {
+            trigger = match writer.recv(rx, Some(deadline)) {
+                Ok(cmd) => writer.accept(cmd),
+                Err(RecvTimeoutError::Timeout) => {
+                    // 超时后一次性读取channel中已有的消息，flume的Receiver::drain()是不阻塞的，总是立即返回
+                    for cmd in rx.drain() {
+                        writer.accept(cmd);
+                    }
+                    Some(FlushTrigger::Deadline)
+                }
+                Err(RecvTimeoutError::Disconnected) => Some(FlushTrigger::Deadline),
+            };
+        }
+        let trigger = trigger.unwrap_or(FlushTrigger::Deadline);
+        debug!("write local batch triggered by {trigger:?}");
+        writer.counters.triggered(trigger);
+
+        let force = trigger == FlushTrigger::Barrier;
+        let mut summary = writer.write_cached(force);
+        // 暂缓的数据在前面的数据写完后接着写入
+        while writer.requeue() {
+            summary = summary.merge(writer.write_cached(force));
+        }
+        writer.handles.close_idle();
+        if writer.shutdown {
+            summary = summary.merge(writer.drop_failed());
+        } else {
+            writer.enforce_budget();
+        }
+        writer.evict_idle_paths();
+        for (path, done) in std::mem::take(&mut writer.flushes) {
+            let slow = writer.flush_slow_lane(path.as_deref());
+            let summary = match &path {
+                Some(path) => summary.only(path),
+                None => summary.clone(),
+            };
+            match slow {
+                // 不阻塞等待慢速通道，由单独的线程合并两边的结果
+                Some(slow) => {
+                    std::thread::spawn(move || {
+                        let summary = match slow.recv() {
+                            Ok(slow) => summary.merge(slow),
+                            Err(_) => summary,
+                        };
+                        let _ = done.send(summary);
+                    });
+                }
+                None => {
+                    let _ = done.send(summary);
+                }
+            }
+        }
+        if writer.shutdown {
+            info!("write local thread shutdown");
+            let summary = writer.close_slow_lane(summary);
+            return (summary, writer.shutdown_done.take());
+        }
+    }
+}
+
+impl Writer {
+    /// 等待下一条消息，最多等到`deadline`
+    ///
+    /// 使用手动推进的时钟时，只有收到消息才会醒来，醒来后按时钟的当前时间判断是否已到`deadline`
+    fn recv(
+        &mut self,
+        rx: &Receiver<Command>,
+        deadline: Option<Instant>,
+    ) -> Result<Command, RecvTimeoutError> {
+        if self.config.clock.is_manual() {
+            if deadline.is_some_and(|deadline| deadline <= self.config.clock.now()) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            // 到期的事件都已处理完，阻塞之前通知时钟推进者
+            #[cfg(feature = "test-util")]
+            for done in self.ticks.drain(..) {
+                let _ = done.send(());
+            }
+            return rx.recv().map_err(|_| RecvTimeoutError::Disconnected);
+        }
+        match deadline {
+            Some(deadline) => rx.recv_deadline(deadline),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// 处理一条消息，要写入的数据合并到cached中。需要立即开始写入时，返回触发写入的原因
+    fn accept(&mut self, cmd: Command) -> Option<FlushTrigger> {
+        let cmd = self.forward(cmd)?;
+        let (f, next) = match cmd {
+            Command::Write(f, d, ack) => {
+                let next = Transferred {
+                    data: d.into(),
+                    acks: Vec::from_iter(ack),
+                    batches: 1,
+                    compressed: 0,
+                    codec: Compression::None,
+                };
+                (f, next)
+            }
+            Command::Transfer(f, t) => (f, *t),
+            Command::Flush { path, done } => {
+                self.flushes.push((path, done));
+                return Some(FlushTrigger::Barrier);
+            }
+            Command::Shutdown(done) => {
+                self.shutdown = true;
+                self.shutdown_done = done;
+                return Some(FlushTrigger::Barrier);
+            }
+            #[cfg(feature = "test-util")]
+            Command::Tick(done) => {
+                self.tick(done);
+                return None;
+            }
+            Command::Configure(f, setting) => {
+                let options = self.options.entry(f).or_default();
+                match setting {
+                    PathSetting::Durability(durability) => options.durability = Some(durability),
+                    PathSetting::Rotation(policy) => {
+                        options.rotation = policy.map(|p| Rotation::new(*p))
+                    }
+                    PathSetting::Compression(compression) => options.compression = compression,
+                    PathSetting::SlowWriteTimeout(timeout) => {
+                        options.slow_write_timeout = Some(timeout)
+                    }
+                }
+                return None;
+            }
+        };
+        // 接收到了空数据想要写入。空的覆盖写入要清空文件，照常写入
+        if next.data.is_empty() {
+            warn!("recv empty data want write to {:?}, skip", f.as_os_str());
+            for ack in next.acks {
+                let report = WriteReport {
+                    path: f.clone(),
+                    bytes: 0,
+                    batches: next.batches,
+                };
+                let _ = ack.send(Ok(report));
+            }
+            return None;
+        }
+
+        self.batch_bytes += next.data.len();
+        self.batch_count += 1;
+
+        // 与尚未写入的数据无法合并(例如追加写入和随机写入交替)时，排队等前面的数据写完
+        let conflict = self.deferred.contains_key(&f)
+            || self
+                .cached
+                .get(&f)
+                .is_some_and(|pending| !pending.can_merge(&next));
+        if conflict {
+            debug!(
+                "defer {} bytes to {:?} until earlier data is written",
+                next.data.len(),
+                f.as_os_str()
+            );
+            self.counters.accepted(false);
+            self.pending_bytes += next.data.len();
+            self.counters
+                .set_pending_bytes(self.worker, self.pending_bytes);
+            self.deferred.entry(f).or_default().push_back(next);
+            return None;
+        }
+
+        let now = self.config.clock.now();
+        let pending = self.cached.entry(f).or_insert_with(|| Pending::new(now));
+        let before = pending.data.len();
+        let merged = pending.merge(next);
+        self.counters.accepted(merged);
+        pending.last_used = now;
+        self.pending_bytes = self.pending_bytes + pending.data.len() - before;
+        self.counters
+            .set_pending_bytes(self.worker, self.pending_bytes);
+
+        let reached = |limit: Option<usize>, n: usize| limit.is_some_and(|limit| n >= limit);
+        if reached(self.config.max_file_bytes, pending.data.len()) {
+            Some(FlushTrigger::FileBytes)
+        } else if reached(self.config.max_batch_bytes, self.batch_bytes) {
+            Some(FlushTrigger::BatchBytes)
+        } else if reached(self.config.max_batch_count, self.batch_count) {
+            Some(FlushTrigger::BatchCount)
+        } else if reached(self.config.max_pending_bytes, self.pending_bytes) {
+            Some(FlushTrigger::MemoryBudget)
+        } else {
+            None
+        }
+    }
+
+    /// 将所有缓存的数据写入本地文件。写入失败后尚未到重试时间的数据，只有`force`时才写入
+    ///
+    /// 需要压缩的数据在单独的线程中压缩，同时写入其它文件的数据，压缩完成后再写入
+    fn write_cached(&mut self, force: bool) -> FlushSummary {
+        let now = self.config.clock.now();
+        let mut summary = FlushSummary::default();
+        self.batch_bytes = 0;
+        self.batch_count = 0;
+
+        // 尚未压缩的数据都在本轮压缩，包括还没到重试时间的，这样放弃写入时交出去的总是压缩后的数据
+        let mut jobs = Vec::new();
+        for (f, pending) in self.cached.iter_mut() {
+            let compression = self
+                .options
+                .get(f)
+                .map_or(Compression::None, |options| options.compression);
+            // 随机写入的数据不压缩
+            let compressed = pending.compressed;
+            let Some(data) = pending.data.payload_mut() else {
+                continue;
+            };
+            if compression.is_none() || compressed >= data.len() {
+                continue;
+            }
+            let raw = data.split_off(compressed);
+            jobs.push((f.clone(), compression, raw));
+        }
+
+        let Writer {
+            worker,
+            config,
+            counters,
+            cached,
+            options,
+            handles,
+            to_quarantine,
+            ..
+        } = self;
+        std::thread::scope(|scope| {
+            let jobs: Vec<_> = jobs
+                .into_iter()
+                .map(|(f, compression, raw)| {
+                    let job = scope.spawn(move || {
+                        // 压缩时panic也按压缩失败处理，数据留在这个线程之外，不会丢失
+                        let res =
+                            panic::catch_unwind(AssertUnwindSafe(|| compression.compress(&raw)))
+                                .unwrap_or_else(|_| Err(io::Error::other("compression panicked")));
+                        (raw, res)
+                    });
+                    (f, compression, job)
+                })
+                .collect();
+
+            // 尽管data部分在每次写入完成之后都会被清空，
+            // 但由于是iter_mut()而不是直接删除HashMap中的所有元素，所以总是存在元素而进入for的迭代，
+            // 因此loop的开头部分需通过阻塞的方式等待可写数据(或者等到重试写入失败的数据)
+            let ready = cached
+                .iter_mut()
+                .filter(|(f, _)| !jobs.iter().any(|(job_path, ..)| job_path == *f))
+                .collect();
+            let mut batch = Batch {
+                worker: *worker,
+                config,
+                counters,
+                options,
+                handles,
+                summary: &mut summary,
+                to_quarantine,
+                now,
+                force,
+            };
+            batch.write_all(ready);
+
+            let mut compressed = HashSet::new();
+            for (f, compression, job) in jobs {
+                let Some(pending) = cached.get_mut(&f) else {
+                    continue;
+                };
+                let (raw, res) = match job.join() {
+                    Ok(done) => done,
+                    Err(payload) => panic::resume_unwind(payload),
+                };
+                let data = pending
+                    .data
+                    .payload_mut()
+                    .expect("only appended or overridden data is compressed");
+                match res {
+                    Ok(compressed) => {
+                        debug!(
+                            "compress {} bytes to {} bytes for {:?}",
+                            raw.len(),
+                            compressed.len(),
+                            f.as_os_str()
+                        );
+                        data.append(compressed.into());
+                        pending.compressed = data.len();
+                        pending.codec = compression;
+                    }
+                    // 压缩失败时数据原样保留，下一轮再尝试压缩
+                    Err(e) => {
+                        error!("failed to compress data for {:?}: {e}", f.as_os_str());
+                        data.append(raw);
+                        continue;
+                    }
+                }
+                compressed.insert(f);
+            }
+            let ready = cached
+                .iter_mut()
+                .filter(|(f, _)| compressed.contains(*f))
+                .collect();
+            let mut batch = Batch {
+                worker: *worker,
+                config,
+                counters,
+                options,
+                handles,
+                summary: &mut summary,
+                to_quarantine,
+                now,
+                force,
+            };
+            batch.write_all(ready);
+        });
+        self.quarantine();
+
+        self.retry_at = self
+            .cached
+            .values()
+            .filter(|pending| !pending.data.is_empty())
+            .filter_map(|pending| pending.retry_at)
+            .min();
+        self.update_pending_bytes();
+
+        summary
+    }
+}
+
+/// 一轮写入中写入单个文件时用到的状态
+struct Batch<'a> {
+    worker: usize,
+    config: &'a Config,
+    counters: &'a Counters,
+    options: &'a mut HashMap<PathBuf, PathOptions>,
+    handles: &'a mut HandleCache,
+    summary: &'a mut FlushSummary,
+    /// 写得太慢，要隔离到慢速通道的文件
+    to_quarantine: &'a mut Vec<PathBuf>,
+    now: Instant,
+    force: bool,
+}
+
+impl Batch<'_> {
+    /// 将各个文件的数据写入本地：追加写入的文件先通过[`Sink::open_batch`]一起打开没有缓存句柄的文件，
+    /// 再通过[`Sink::append_batch`]一次提交，覆盖写入、随机写入和设置了写入超时的文件逐个写入
+    fn write_all(&mut self, ready: Vec<(&PathBuf, &mut Pending)>) {
+        let mut opening = Vec::new();
+        for (f, pending) in ready {
+            if !self.due(pending) {
+                continue;
+            }
+            if !matches!(pending.data, Merged::Append(_)) || self.timeout(f).is_some() {
+                self.write(f, pending);
+                continue;
+            }
+            pending.attempts += 1;
+            let started = Instant::now();
+            self.rotate(f, pending.data.len());
+            opening.push((f, pending, started));
+        }
+        let paths: Vec<_> = opening.iter().map(|(f, ..)| f.as_path()).collect();
+        let opened = self.handles.checkout_batch(&paths);
+        let mut appends = Vec::new();
+        let mut handles = Vec::new();
+        for ((f, pending, started), handle) in opening.into_iter().zip(opened) {
+            match handle {
+                Ok(handle) => {
+                    appends.push((f, pending, started));
+                    handles.push(handle);
+                }
+                Err(e) => self.finish(f, pending, Err(e), started, None),
+            }
+        }
+        if appends.is_empty() {
+            return;
+        }
+
+        let sink = self.config.sink.0.as_ref();
+        let durabilities: Vec<_> = appends.iter().map(|(f, ..)| self.durability(f)).collect();
+        let slices: Vec<_> = appends
+            .iter()
+            .map(|(_, pending, _)| match &pending.data {
+                Merged::Append(data) => data.io_slices(0),
+                _ => unreachable!("only appends are batched"),
+            })
+            .collect();
+        let mut batch: Vec<_> = handles
+            .iter_mut()
+            .zip(&slices)
+            .zip(&durabilities)
+            .map(|((handle, bufs), durability)| BatchAppend {
+                file: handle.file.as_mut(),
+                bufs,
+                sync: durability.file_sync(),
+                written: 0,
+                result: Ok(()),
+            })
+            .collect();
+        sink.append_batch(&mut batch);
+        let results: Vec<_> = batch
+            .into_iter()
+            .map(|append| (append.written, append.result))
+            .collect();
+        drop(slices);
+
+        let iter = appends.into_iter().zip(handles).zip(durabilities);
+        for ((((f, pending, started), mut handle), durability), (n, res)) in iter.zip(results) {
+            let Merged::Append(data) = &mut pending.data else {
+                unreachable!("only appends are batched");
+            };
+            data.advance(n);
+            pending.written += n;
+            // 只写入了一部分时逐个写完剩下的数据，再按原来的方式同步
+            let file = handle.file.as_mut();
+            let res = res.and_then(|()| match data.is_empty() {
+                true => durability.after_file_sync(file, f, sink),
+                false => {
+                    write_all_drain(file, data, &mut pending.written)?;
+                    durability.sync(file, f, sink)
+                }
+            });
+            if res.is_ok() {
+                info!("append {} bytes to {:?}", pending.written, f.as_os_str());
+                self.handles.checkin(f, handle);
+            }
+            self.finish(f, pending, res, started, None);
+        }
+    }
+
+    /// 是否到了写入`pending`的时候
+    fn due(&self, pending: &Pending) -> bool {
+        // 某个文件接收到数据后，其它缓存的路径下可能没有要写的数据，因此跳过空的
+        if pending.data.is_empty() {
+            return false;
+        }
+        self.force || pending.retry_at.is_none_or(|retry| retry <= self.now)
+    }
+
+    fn durability(&self, f: &Path) -> Durability {
+        self.options
+            .get(f)
+            .and_then(|options| options.durability)
+            .unwrap_or(self.config.durability)
+    }
+
+    fn timeout(&self, f: &Path) -> Option<Duration> {
+        self.options
+            .get(f)
+            .and_then(|options| options.slow_write_timeout)
+            .or(self.config.slow_write_timeout)
+    }
+
+    /// 即将向`f`追加`incoming`字节时，按需轮转。轮转失败时继续写入原文件，不影响数据的写入
+    fn rotate(&mut self, f: &Path, incoming: usize) {
+        let rotation = self.options.get_mut(f).and_then(|o| o.rotation.as_mut());
+        if let Some(rotation) = rotation {
+            let sink = self.config.sink.0.as_ref();
+            let now = self.config.clock.wall();
+            match rotation.rotate_if_needed(sink, f, incoming as u64, now) {
+                Ok(true) => self.handles.close(f),
+                Ok(false) => {}
+                Err(e) => error!("failed to rotate {:?}: {e}", f.as_os_str()),
+            }
+        }
+    }
+
+    /// 将`pending`中的数据写入`f`
+    fn write(&mut self, f: &Path, pending: &mut Pending) {
+        pending.attempts += 1;
+        let started = Instant::now();
+        let durability = self.durability(f);
+        let timeout = self.timeout(f);
+        let sink = self.config.sink.0.as_ref();
+        let res = match &mut pending.data {
+            Merged::Override(data) => {
+                let res = if self.config.atomic_override {
+                    write_atomic(sink, f, data, self.config.preserve)
+                } else {
+                    write_override(sink, f, data, durability)
+                };
+                res.map(|_| {
+                    info!("override {} bytes to {:?}", data.len(), f.as_os_str());
+                    pending.written = data.len();
+                    *data = Payload::new();
+                })
+            }
+            Merged::Append(data) => {
+                self.rotate(f, data.len());
+                self.handles
+                    .with_file(f, OpenMode::Append, timeout, |file| {
+                        write_all_drain(file, data, &mut pending.written)?;
+                        info!("append {} bytes to {:?}", pending.written, f.as_os_str());
+                        durability.sync(file, f, sink)
+                    })
+            }
+            Merged::WriteAt(ranges) => {
+                self.handles
+                    .with_file(f, OpenMode::Positional, timeout, |file| {
+                        write_ranges(file, ranges, &mut pending.written)?;
+                        info!(
+                            "write {} bytes at positions to {:?}",
+                            pending.written,
+                            f.as_os_str()
+                        );
+                        durability.sync(file, f, sink)
+                    })
+            }
+        };
+        self.finish(f, pending, res, started, timeout);
+    }
+
+    /// 处理写入`f`的结果：通知等待写入结果的调用者，写入失败时安排重试或者放弃，
+    /// 写得太慢时将`f`隔离到慢速通道
+    fn finish(
+        &mut self,
+        f: &Path,
+        pending: &mut Pending,
+        res: io::Result<()>,
+        started: Instant,
+        timeout: Option<Duration>,
+    ) {
+        let now = self.now;
+        pending.compressed = pending.compressed.min(pending.data.len());
+        let bytes = res.as_ref().ok().map(|_| pending.written);
+        let elapsed = started.elapsed();
+        self.counters.wrote(self.worker, f, bytes, elapsed);
+
+        // 打开文件超时或写得太慢的文件隔离到慢速通道，未写入的数据也交给慢速通道，这里不再重试。
+        // 存储本身返回的超时错误按普通错误重试
+        let timed_out = matches!(&res, Err(e) if is_open_timeout(e));
+        let slow = timed_out || timeout.is_some_and(|timeout| elapsed > timeout);
+        if slow && !self.counters.is_slow_lane(self.worker) {
+            warn!(
+                "writing {:?} took {elapsed:?}, exceeding write timeout {timeout:?}",
+                f.as_os_str()
+            );
+            self.to_quarantine.push(f.to_path_buf());
+            if res.is_err() {
+                return;
+            }
+        }
+        match res {
+            Ok(()) => {
+                let n = pending.written;
+                for ack in pending.acks.drain(..) {
+                    let report = WriteReport {
+                        path: f.to_path_buf(),
+                        bytes: n,
+                        batches: pending.batches,
+                    };
+                    let _ = ack.send(Ok(report));
+                }
+                self.summary.written.push((f.to_path_buf(), n));
+                // 覆盖写入完成后不再有要写入的数据，清空的覆盖写入不能留下来
+                if let Merged::Override(_) = pending.data {
+                    pending.data = Merged::Append(Payload::new());
+                }
+                pending.reset();
+                pending.last_used = now;
+            }
+            // 未写入的数据保留在cached中，稍后重试，等待写入结果的调用者继续等待
+            Err(e) if self.config.retry.should_retry(&e, pending.attempts) => {
+                let backoff = self.config.retry.backoff_after(pending.attempts);
+                error!(
+                    "{e}, {} bytes kept for retry in {backoff:?}",
+                    pending.data.len()
+                );
+                let e = Arc::new(e);
+                pending.error = Some(e.clone());
+                pending.retry_at = Some(now + backoff);
+                self.summary.failed.push((f.to_path_buf(), e));
+            }
+            Err(e) => {
+                let e = Arc::new(e);
+                pending.error = Some(e.clone());
+                give_up(f, pending, self.config, self.counters);
+                self.summary.failed.push((f.to_path_buf(), e));
+            }
+        }
+    }
+}
+
+impl Writer {
+    /// 被隔离的文件的消息直接转发给慢速通道，否则原样返回
+    fn forward(&mut self, cmd: Command) -> Option<Command> {
+        let path = match &cmd {
+            Command::Write(path, ..)
+            | Command::Transfer(path, _)
+            | Command::Configure(path, _)
+            | Command::Flush {
+                path: Some(path), ..
+            } => path,
+            _ => return Some(cmd),
+        };
+        if !self.quarantined.contains(path) {
+            return Some(cmd);
+        }
+        // 慢速通道没有写入超时，不转发写入超时的设置
+        if let Command::Configure(_, PathSetting::SlowWriteTimeout(_)) = cmd {
+            return None;
+        }
+        let slow_lane = self.slow_lane.as_ref()?;
+        let _ = slow_lane.tx.send(cmd);
+        None
+    }
+
+    /// 让慢速通道也写完已收到的数据，返回接收其写入结果的channel。
+    /// `path`未被隔离时无需等待慢速通道
+    fn flush_slow_lane(&self, path: Option<&Path>) -> Option<Receiver<FlushSummary>> {
+        let slow_lane = self.slow_lane.as_ref()?;
+        if path.is_some_and(|path| !self.quarantined.contains(path)) {
+            return None;
+        }
+        let (done, rx) = bounded(1);
+        let path = path.map(Path::to_path_buf);
+        slow_lane.tx.send(Command::Flush { path, done }).ok()?;
+        Some(rx)
+    }
+
+    /// 慢速通道也处理完到期的事件后，才通知时钟推进者
+    #[cfg(feature = "test-util")]
+    fn tick(&mut self, done: Sender<()>) {
+        if let Some(slow_lane) = &self.slow_lane {
+            let (slow_done, rx) = bounded(1);
+            if slow_lane.tx.send(Command::Tick(slow_done)).is_ok() {
+                let _ = rx.recv();
+            }
+        }
+        self.ticks.push(done);
+    }
+
+    /// 将本轮写得太慢的文件隔离到慢速通道：它的设置和尚未写入的数据都交给慢速通道，
+    /// 此后收到的该文件的消息也都转发过去。慢速通道自身不再隔离文件
+    fn quarantine(&mut self) {
+        let to_quarantine = std::mem::take(&mut self.to_quarantine);
+        if self.counters.is_slow_lane(self.worker) {
+            return;
+        }
+        for f in to_quarantine {
+            if self.slow_lane.is_none() {
+                self.slow_lane = SlowLane::spawn(&self.config, &self.counters, self.worker);
+            }
+            let Some(slow_lane) = &self.slow_lane else {
+                return;
+            };
+            warn!("quarantine {:?} to slow lane", f.as_os_str());
+            self.counters.quarantined();
+            self.handles.close(&f);
+
+            if let Some(options) = self.options.remove(&f) {
+                let mut settings = Vec::new();
+                settings.extend(options.durability.map(PathSetting::Durability));
+                settings.extend(
+                    options.rotation.map(|rotation| {
+                        PathSetting::Rotation(Some(Box::new(rotation.into_policy())))
+                    }),
+                );
+                if !options.compression.is_none() {
+                    settings.push(PathSetting::Compression(options.compression));
+                }
+                for setting in settings {
+                    let _ = slow_lane.tx.send(Command::Configure(f.clone(), setting));
+                }
+            }
+            let mut transfers = Vec::new();
+            if let Some(pending) = self.cached.remove(&f) {
+                if !pending.data.is_empty() {
+                    transfers.push(Transferred {
+                        data: pending.data,
+                        acks: pending.acks,
+                        batches: pending.batches,
+                        compressed: pending.compressed,
+                        codec: pending.codec,
+                    });
+                }
+            }
+            // 暂缓的数据排在后面按顺序转交
+            transfers.extend(self.deferred.remove(&f).into_iter().flatten());
+            for transferred in transfers {
+                let _ = slow_lane
+                    .tx
+                    .send(Command::Transfer(f.clone(), Box::new(transferred)));
+            }
+            self.quarantined.insert(f);
+        }
+    }
+
+    /// 本线程退出前，关闭慢速通道并合并其最后一轮写入的结果
+    fn close_slow_lane(&mut self, summary: FlushSummary) -> FlushSummary {
+        match self.slow_lane.take() {
+            Some(slow_lane) => summary.merge(slow_lane.close()),
+            None => summary,
+        }
+    }
+}
+
+impl Writer {
+    /// 退出前仍未能写入的数据只能放弃，暂缓的数据也依次放弃。
+    /// 最后一轮写入失败的数据已在其汇总结果中，返回的汇总结果只包含放弃的暂缓数据
+    fn drop_failed(&mut self) -> FlushSummary {
+        let mut summary = FlushSummary::default();
+        let mut errors = HashMap::new();
+        let mut deferred = false;
+        loop {
+            for (f, pending) in self.cached.iter_mut() {
+                if pending.data.is_empty() {
+                    continue;
+                }
+                // 暂缓的数据没有写入过，沿用前面的数据写入失败的原因
+                if pending.error.is_none() {
+                    pending.error = errors.get(f).cloned();
+                }
+                let error = give_up(f, pending, &self.config, &self.counters);
+                if deferred {
+                    summary.failed.push((f.clone(), error.clone()));
+                }
+                errors.insert(f.clone(), error);
+            }
+            if !self.requeue() {
+                break;
+            }
+            deferred = true;
+        }
+        self.update_pending_bytes();
+        summary
+    }
+
+    /// 将暂缓的数据按到达顺序合并到cached中，直到遇到仍无法合并的数据。有数据合并进来时返回true
+    fn requeue(&mut self) -> bool {
+        let mut requeued = false;
+        self.deferred.retain(|f, queue| {
+            let Some(pending) = self.cached.get_mut(f) else {
+                return true;
+            };
+            while let Some(next) = queue.pop_front() {
+                if !pending.can_merge(&next) {
+                    queue.push_front(next);
+                    break;
+                }
+                pending.merge(next);
+                requeued = true;
+            }
+            !queue.is_empty()
+        });
+        requeued
+    }
+}
+
+impl Writer {
+    /// cached中所有待写数据超出字节数上限时，说明写入失败而保留下来的数据太多，
+    /// 从数据最多的文件开始，放弃写入这些数据，直到不超出上限
+    fn enforce_budget(&mut self) {
+        let Some(max) = self.config.max_pending_bytes else {
+            return;
+        };
+        if self.pending_bytes <= max {
+            return;
+        }
+
+        let mut failed: Vec<_> = self
+            .cached
+            .iter()
+            .filter(|(_, pending)| !pending.data.is_empty())
+            .map(|(f, pending)| (pending.data.len(), f.clone()))
+            .collect();
+        failed.sort_unstable_by(|a, b| b.cmp(a));
+        for (len, f) in failed {
+            if self.pending_bytes <= max {
+                break;
+            }
+            warn!(
+                "write local pending bytes exceed {max}, give up {len} bytes to {:?}",
+                f.as_os_str()
+            );
+            if let Some(pending) = self.cached.get_mut(&f) {
+                give_up(&f, pending, &self.config, &self.counters);
+            }
+            self.pending_bytes -= len;
+        }
+        self.retry_at = self
+            .cached
+            .values()
+            .filter(|pending| !pending.data.is_empty())
+            .filter_map(|pending| pending.retry_at)
+            .min();
+        self.update_pending_bytes();
+    }
+
+    /// 最早的没有待写数据的文件闲置超时的时间点
+    fn idle_path_deadline(&self) -> Option<Instant> {
+        self.cached
+            .values()
+            .filter(|pending| pending.data.is_empty())
+            .map(|pending| pending.last_used + self.config.idle_path_timeout)
+            .min()
+    }
+
+    /// 移除没有待写数据且闲置超时的文件，避免写过的文件越来越多时cached无限增长
+    fn evict_idle_paths(&mut self) {
+        let now = self.config.clock.now();
+        let idle = self.config.idle_path_timeout;
+        self.cached.retain(|_, pending| {
+            !pending.data.is_empty() || now.duration_since(pending.last_used) < idle
+        });
+        self.counters
+            .retain_paths(self.worker, |path| self.cached.contains_key(path));
+        self.publish_memory();
+    }
+
+    fn update_pending_bytes(&mut self) {
+        let deferred = self.deferred.values().flatten();
+        self.pending_bytes = self
+            .cached
+            .values()
+            .map(|pending| pending.data.len())
+            .sum::<usize>()
+            + deferred.map(|next| next.data.len()).sum::<usize>();
+        self.publish_memory();
+    }
+
+    fn publish_memory(&self) {
+        let deferred = self.deferred.values().flatten();
+        let capacity = self
+            .cached
+            .values()
+            .map(|pending| pending.data.capacity())
+            .chain(deferred.map(|next| next.data.capacity()))
+            .sum();
+        self.counters
+            .set_memory(self.worker, self.cached.len(), self.pending_bytes, capacity);
+    }
+}
+
+impl Pending {
+    /// 能否按到达顺序合并后一批数据`next`。已经压缩过的数据(例如重新写入的溢出数据)
+    /// 只能接在全部已压缩的数据之后，压缩过的部分总是在开头
+    fn can_merge(&self, next: &Transferred) -> bool {
+        if self.data.is_empty() || matches!(next.data, Merged::Override(_)) {
+            return true;
+        }
+        self.data.can_merge(&next.data)
+            && (next.compressed == 0 || self.compressed == self.data.len())
+    }
+
+    /// 按到达顺序合并同一文件的后一批数据，需要先通过[`Pending::can_merge`]确认可以合并。
+    /// 返回是否与已有的数据合并
+    fn merge(&mut self, next: Transferred) -> bool {
+        let merged = !self.data.is_empty();
+        if !merged || matches!(next.data, Merged::Override(_)) {
+            self.compressed = next.compressed;
+            self.codec = next.codec;
+        } else if next.compressed > 0 {
+            self.compressed += next.compressed;
+            self.codec = next.codec;
+        }
+        if merged {
+            self.data.merge(next.data);
+        } else {
+            // 已写完的文件缓存的是清空后的数据，其类型是上一轮遗留的，需用新数据直接替换
+            self.data = next.data;
+        }
+        self.batches += next.batches;
+        self.acks.extend(next.acks);
+        merged
+    }
+
+    /// 数据全部写入或被放弃后，重置写入状态
+    fn reset(&mut self) {
+        self.batches = 0;
+        self.written = 0;
+        self.error = None;
+        self.attempts = 0;
+        self.retry_at = None;
+    }
+}
+
+/// 放弃写入`pending`中的数据：优先将数据写入溢出目录，写不了时交给dead letter回调，
+/// 并通知等待写入结果的调用者。随机写入的每一段、已压缩和尚未压缩的部分各自溢出或交给回调。
+/// 返回放弃的原因
+fn give_up(
+    f: &Path,
+    pending: &mut Pending,
+    config: &Config,
+    counters: &Counters,
+) -> Arc<io::Error> {
+    counters.gave_up();
+    let error = pending
+        .error
+        .take()
+        .unwrap_or_else(|| Arc::new(io::ErrorKind::Other.into()));
+    let data = std::mem::replace(&mut pending.data, Merged::Append(Payload::new()));
+    let len = data.len();
+
+    let mut spill_files = Vec::new();
+    let mut dead = Vec::new();
+    for (data, compressed) in data.into_write_data(pending.compressed) {
+        let codec = match compressed {
+            true => pending.codec,
+            false => Compression::None,
+        };
+        let spilled = config.spill_dir.as_ref().and_then(|dir| {
+            spill::spill(dir, f, &data, codec, error.as_ref())
+                .inspect_err(|e| error!("{e}"))
+                .ok()
+        });
+        match spilled {
+            Some(spill_file) => spill_files.push(spill_file),
+            None => dead.push(data),
+        }
+    }
+    let err = match spill_files.first() {
+        Some(spill_file) if dead.is_empty() => {
+            warn!(
+                "give up writing {len} bytes to {:?} after {} attempts, spilled to {:?}",
+                f.as_os_str(),
+                pending.attempts,
+                spill_file.as_os_str()
+            );
+            WriteError::Spilled {
+                path: f.to_path_buf(),
+                spill_file: spill_file.clone(),
+            }
+        }
+        _ => {
+            error!(
+                "give up writing {len} bytes to {:?} after {} attempts",
+                f.as_os_str(),
+                pending.attempts
+            );
+            if let Some(DeadLetterHandler(handler)) = &config.dead_letter {
+                for data in dead {
+                    handler(DeadLetter {
+                        path: f.to_path_buf(),
+                        data,
+                        error: error.clone(),
+                        attempts: pending.attempts,
+                    });
+                }
+            }
+            WriteError::Io {
+                path: f.to_path_buf(),
+                source: error.clone(),
+            }
+        }
+    };
+    for ack in pending.acks.drain(..) {
+        let _ = ack.send(Err(err.clone()));
+    }
+    pending.compressed = 0;
+    pending.reset();
+    error
+}
+
+/// 将`data`全部写入`file`，已写入的部分从`data`中移除并累加到`written`。
+/// 出错时`data`中只剩下未写入的部分
+fn write_all_drain(
+    file: &mut dyn SinkFile,
+    data: &mut Payload,
+    written: &mut usize,
+) -> io::Result<()> {
+    let mut n = 0;
+    let res = write_all(file, data, &mut n);
+    data.advance(n);
+    *written += n;
+    res
+}
+
+/// 将各段数据依次写到文件中的指定位置，已写入的部分从`ranges`中移除并累加到`written`。
+/// 出错时`ranges`中只剩下未写入的部分
+fn write_ranges(
+    file: &mut dyn SinkFile,
+    ranges: &mut Vec<(u64, Payload)>,
+    written: &mut usize,
+) -> io::Result<()> {
+    while let Some((offset, data)) = ranges.first_mut() {
+        while !data.is_empty() {
+            let chunk = data.chunks().next().unwrap_or_default();
+            let n = match file.write_at(chunk, *offset) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            data.advance(n);
+            *offset += n as u64;
+            *written += n;
+        }
+        ranges.remove(0);
+    }
+    Ok(())
+}
+
+/// 截断`path`后写入`data`
+fn write_override(
+    sink: &dyn Sink,
+    path: &Path,
+    data: &Payload,
+    durability: Durability,
+) -> io::Result<()> {
+    let mut file = sink.create(path)?;
+    write_all(file.as_mut(), data, &mut 0)?;
+    durability.sync(file.as_mut(), path, sink)
+}
@@ -0,0 +1,51 @@
+use std::{io, time::Duration};
+use write_local::{test_util::TestLocal, RetryPolicy, WriteData, WriteError, WriteLocal};
+
+#[test]
+fn merged_writes_report_the_whole_batch() {
+    let local = TestLocal::new();
+    let first = local.write_with_ack("a.log".into(), WriteData::Append(b"12".to_vec().into()));
+    let second = local.write_with_ack("a.log".into(), WriteData::Append(b"3".to_vec().into()));
+    let alone = local.write_with_ack("b.log".into(), WriteData::Append(b"x".to_vec().into()));
+    assert!(first.try_wait().is_none());
+
+    local.advance(Duration::from_millis(100));
+    for ack in [first, second] {
+        let report = ack.wait().unwrap();
+        assert_eq!(report.path.to_str(), Some("a.log"));
+        assert_eq!(report.bytes, 3);
+        assert_eq!(report.batches, 2);
+        assert!(report.merged());
+    }
+    let report = alone.wait().unwrap();
+    assert_eq!(report.bytes, 1);
+    assert!(!report.merged());
+}
+
+#[test]
+fn failed_write_is_reported_to_caller() {
+    let local = TestLocal::with_builder(WriteLocal::builder().retry_policy(RetryPolicy::never()));
+    local
+        .sink()
+        .fail_open("a.log", Some(io::ErrorKind::PermissionDenied));
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+
+    local.advance(Duration::from_millis(100));
+    match ack.wait() {
+        Err(WriteError::Io { path, source }) => {
+            assert_eq!(path.to_str(), Some("a.log"));
+            assert_eq!(source.kind(), io::ErrorKind::PermissionDenied);
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(local.read("a.log"), None);
+}
+
+#[test]
+fn empty_write_is_acknowledged() {
+    let local = TestLocal::new();
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(Vec::new().into()));
+    local.advance(Duration::ZERO);
+    assert_eq!(ack.wait().unwrap().bytes, 0);
+    assert_eq!(local.read("a.log"), None);
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use common::panic_on_open;
+use std::{
+    future::Future,
+    path::Path,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+use write_local::{MemorySink, WriteData, WriteLocal};
+
+/// 在当前线程上运行`future`直到完成，不依赖任何async运行时
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+fn local(sink: &MemorySink) -> WriteLocal {
+    WriteLocal::builder()
+        .sink(sink.clone())
+        .batch_window(Duration::from_secs(3600))
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn async_writes_are_flushed() {
+    let sink = MemorySink::default();
+    let local = local(&sink);
+    block_on(async {
+        local
+            .write_async("a.log".into(), WriteData::Append(b"1".to_vec().into()))
+            .await;
+        let ack = local
+            .write_with_ack_async("a.log".into(), WriteData::Append(b"2".to_vec().into()))
+            .await;
+        local
+            .write_async("b.log".into(), WriteData::Append(b"x".to_vec().into()))
+            .await;
+
+        let summary = local.flush_path_async(Path::new("a.log")).await.unwrap();
+        assert_eq!(summary.written.len(), 1);
+        let report = ack.wait_async().await.unwrap();
+        assert_eq!(report.bytes, 2);
+        assert_eq!(report.batches, 2);
+
+        local
+            .write_async("b.log".into(), WriteData::Append(b"y".to_vec().into()))
+            .await;
+        let summary = local.flush_async().await.unwrap();
+        assert_eq!(summary.written.len(), 1);
+    });
+    assert_eq!(sink.read("a.log").unwrap(), b"12");
+    assert_eq!(sink.read("b.log").unwrap(), b"xy");
+}
+
+#[test]
+fn async_shutdown_writes_remaining_data() {
+    let sink = MemorySink::default();
+    let local = local(&sink);
+    block_on(async {
+        local
+            .write_async("a.log".into(), WriteData::Append(b"1".to_vec().into()))
+            .await;
+        let summary = local.shutdown_async().await.unwrap();
+        assert_eq!(summary.written.len(), 1);
+        assert!(local.shutdown_async().await.is_none());
+        assert!(local.flush_async().await.is_none());
+    });
+    assert_eq!(sink.read("a.log").unwrap(), b"1");
+}
+
+#[test]
+fn async_flush_returns_none_when_one_worker_panics() {
+    let local = WriteLocal::builder()
+        .sink(panic_on_open(MemorySink::default(), "boom.log"))
+        .worker_threads(8)
+        .batch_window(Duration::from_secs(3600))
+        .build()
+        .unwrap();
+    block_on(async {
+        for f in 0..16 {
+            local
+                .write_async(
+                    format!("{f}.log").into(),
+                    WriteData::Append(b"1".to_vec().into()),
+                )
+                .await;
+        }
+        local
+            .write_async("boom.log".into(), WriteData::Append(b"1".to_vec().into()))
+            .await;
+        assert!(local.flush_async().await.is_none());
+        assert!(local.flush_async().await.is_none());
+        assert!(local.shutdown_async().await.is_none());
+    });
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{test_dir, Hooked};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use write_local::{FsSink, MemorySink, RetryPolicy, Sink, WriteData, WriteError, WriteLocal};
+
+fn local(sink: impl Sink + 'static) -> WriteLocal {
+    WriteLocal::builder()
+        .sink(sink)
+        .retry_policy(RetryPolicy::never())
+        .build()
+        .unwrap()
+}
+
+fn replace(data: &[u8]) -> WriteData {
+    WriteData::Override(data.to_vec().into())
+}
+
+#[test]
+fn override_replaces_file_by_rename() {
+    let sink = MemorySink::default();
+    sink.create(Path::new("a.json"))
+        .unwrap()
+        .append(b"old content")
+        .unwrap();
+    let before = sink.metadata(Path::new("a.json")).unwrap().id;
+    let local = local(sink.clone());
+
+    local.write("a.json".into(), replace(b"new"));
+    assert!(local.flush().unwrap().failed.is_empty());
+    assert_eq!(sink.read("a.json").unwrap(), b"new");
+    // 写入的是另一个文件，改名后替换了原文件
+    assert_ne!(sink.metadata(Path::new("a.json")).unwrap().id, before);
+    assert_eq!(sink.paths(), [PathBuf::from("a.json")]);
+}
+
+#[test]
+fn failed_rename_keeps_original() {
+    let sink = MemorySink::default();
+    sink.create(Path::new("a.json"))
+        .unwrap()
+        .append(b"old")
+        .unwrap();
+    let local = local(sink.clone());
+
+    // 改名为目标文件时失败，临时文件被删除，原文件不受影响
+    sink.fail_open("a.json", Some(io::ErrorKind::PermissionDenied));
+    let ack = local.write_with_ack("a.json".into(), replace(b"new"));
+    assert_eq!(local.flush().unwrap().failed.len(), 1);
+    assert!(matches!(ack.wait(), Err(WriteError::Io { .. })));
+    assert_eq!(sink.read("a.json").unwrap(), b"old");
+    assert_eq!(sink.paths(), [PathBuf::from("a.json")]);
+
+    sink.fail_open("a.json", None);
+    local.write("a.json".into(), replace(b"new"));
+    assert!(local.flush().unwrap().failed.is_empty());
+    assert_eq!(sink.read("a.json").unwrap(), b"new");
+}
+
+#[test]
+fn failed_rename_leaves_no_tmp_file_on_disk() {
+    let dir = test_dir("atomic-rename");
+    let path = dir.join("a.json");
+    std::fs::write(&path, b"old").unwrap();
+    // 改名总是失败
+    let local =
+        local(Hooked::new(FsSink).on_rename(|_, _| Err(io::ErrorKind::PermissionDenied.into())));
+
+    local.write(path.clone(), replace(b"new"));
+    assert_eq!(local.flush().unwrap().failed.len(), 1);
+    assert_eq!(std::fs::read(&path).unwrap(), b"old");
+    let names: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["a.json"]);
+
+    local.shutdown();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn override_preserves_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("atomic-permissions");
+    let kept = dir.join("kept.json");
+    let reset = dir.join("reset.json");
+    for path in [&kept, &reset] {
+        std::fs::write(path, b"old").unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o604)).unwrap();
+    }
+    let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    let local = local(FsSink);
+    local.write(kept.clone(), replace(b"new"));
+    local.shutdown().unwrap();
+    assert_eq!(std::fs::read(&kept).unwrap(), b"new");
+    assert_eq!(mode(&kept), 0o604);
+
+    // 关闭后使用临时文件自己的权限
+    let local = WriteLocal::builder()
+        .preserve_permissions(false)
+        .build()
+        .unwrap();
+    local.write(reset.clone(), replace(b"new"));
+    local.shutdown().unwrap();
+    assert_eq!(std::fs::read(&reset).unwrap(), b"new");
+    assert_ne!(mode(&reset), 0o604);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use common::{append, Hooked};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use write_local::{
+    test_util::TestLocal, Durability, MemorySink, RetryPolicy, Sink, WriteData, WriteError,
+    WriteLocal,
+};
+
+#[test]
+fn batch_window_merges_writes() {
+    let local = TestLocal::new();
+    append(&local, "a.log", b"1");
+    append(&local, "b.log", b"x");
+    append(&local, "a.log", b"2");
+
+    local.advance(Duration::from_millis(99));
+    assert_eq!(local.read("a.log"), None);
+
+    local.advance(Duration::from_millis(1));
+    assert_eq!(local.read("a.log").unwrap(), b"12");
+    assert_eq!(local.read("b.log").unwrap(), b"x");
+    assert_eq!(local.trigger_counts().deadline, 1);
+    assert_eq!(local.stats().merged, 1);
+}
+
+#[test]
+fn settings_do_not_start_a_batch() {
+    let local = TestLocal::new();
+    local.set_durability("a.log".into(), Durability::Fsync);
+    local.set_rotation("a.log".into(), None);
+    local.advance(Duration::from_secs(1));
+    assert_eq!(local.stats().batches, 0);
+    assert_eq!(local.trigger_counts().deadline, 0);
+
+    // 收集等待从第一条数据开始计时
+    local.set_durability("a.log".into(), Durability::Fdatasync);
+    local.advance(Duration::from_millis(50));
+    append(&local, "a.log", b"1");
+    local.advance(Duration::from_millis(99));
+    assert_eq!(local.read("a.log"), None);
+    local.advance(Duration::from_millis(1));
+    assert_eq!(local.read("a.log").unwrap(), b"1");
+    assert_eq!(local.stats().batches, 1);
+}
+
+#[test]
+fn batch_count_triggers_without_waiting() {
+    let local = TestLocal::with_builder(WriteLocal::builder().max_batch_count(2));
+    append(&local, "a.log", b"1");
+    append(&local, "a.log", b"2");
+    // 不推进时钟，只等待后台写线程处理完已发送的消息
+    local.advance(Duration::ZERO);
+    assert_eq!(local.read("a.log").unwrap(), b"12");
+    assert_eq!(local.trigger_counts().batch_count, 1);
+}
+
+#[test]
+fn run_batch_writes_immediately() {
+    let local = TestLocal::new();
+    append(&local, "a.log", b"1");
+    local.write("c.json".into(), WriteData::Override(b"{}".to_vec().into()));
+    let summary = local.run_batch().unwrap();
+    assert_eq!(summary.written.len(), 2);
+    assert_eq!(local.read("a.log").unwrap(), b"1");
+    assert_eq!(local.read("c.json").unwrap(), b"{}");
+    assert_eq!(local.trigger_counts().barrier, 1);
+    assert_eq!(local.sink().paths().len(), 2);
+}
+
+#[test]
+fn each_window_is_a_separate_batch() {
+    let local = TestLocal::new();
+    for data in [b"1", b"2", b"3"] {
+        append(&local, "a.log", data);
+        local.advance(Duration::from_millis(100));
+    }
+    assert_eq!(local.read("a.log").unwrap(), b"123");
+    assert_eq!(local.trigger_counts().deadline, 3);
+    assert_eq!(local.stats().batches, 3);
+}
+
+#[test]
+fn workers_keep_per_file_order() {
+    let local = TestLocal::with_builder(WriteLocal::builder().worker_threads(4));
+    for i in 0..10 {
+        for f in 0..8 {
+            append(&local, &format!("{f}.log"), i.to_string().as_bytes());
+        }
+    }
+    local.advance(Duration::from_millis(100));
+    for f in 0..8 {
+        assert_eq!(local.read(format!("{f}.log")).unwrap(), b"0123456789");
+    }
+    assert_eq!(local.stats().paths.len(), 8);
+    assert_eq!(local.memory_stats().cached_paths, 8);
+
+    append(&local, "0.log", b"!");
+    let summary = local.shutdown().unwrap();
+    assert_eq!(summary.written.len(), 1);
+    assert_eq!(local.read("0.log").unwrap(), b"0123456789!");
+}
+
+#[test]
+fn slow_path_is_quarantined() {
+    let sink = MemorySink::default();
+    let local = WriteLocal::builder()
+        .sink(Hooked::new(sink.clone()).on_open(|inner, path| {
+            if path == Path::new("slow.log") {
+                thread::sleep(Duration::from_millis(300));
+            }
+            inner.open(path)
+        }))
+        .slow_write_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+    local.write("slow.log".into(), WriteData::Append(b"1".to_vec().into()));
+    local.write("fast.log".into(), WriteData::Append(b"a".to_vec().into()));
+    local.flush();
+    assert_eq!(local.stats().quarantined, 1);
+
+    // 慢速文件不再拖慢其它文件，其数据仍然按顺序写入
+    local.write("slow.log".into(), WriteData::Append(b"2".to_vec().into()));
+    local.write("fast.log".into(), WriteData::Append(b"b".to_vec().into()));
+    let started = Instant::now();
+    local.flush_path(Path::new("fast.log"));
+    assert!(started.elapsed() < Duration::from_millis(250));
+    assert_eq!(sink.read("fast.log").unwrap(), b"ab");
+
+    let summary = local.shutdown().unwrap();
+    assert!(summary.failed.is_empty());
+    assert_eq!(sink.read("slow.log").unwrap(), b"12");
+}
+
+#[test]
+fn storage_timeout_is_not_quarantined() {
+    let sink = MemorySink::default();
+    let dead = Arc::new(Mutex::new(Vec::new()));
+    let dead_letters = dead.clone();
+    let local = WriteLocal::builder()
+        .sink(sink.clone())
+        .retry_policy(RetryPolicy::never())
+        .dead_letter(move |letter| dead_letters.lock().unwrap().push(letter.path))
+        .build()
+        .unwrap();
+    // 存储本身返回的超时(例如NFS的ETIMEDOUT)按普通的写入失败处理
+    sink.fail_open("a.log", Some(io::ErrorKind::TimedOut));
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+    let summary = local.flush().unwrap();
+    assert_eq!(summary.failed.len(), 1);
+    assert!(matches!(ack.wait(), Err(WriteError::Io { .. })));
+    assert_eq!(*dead.lock().unwrap(), vec![PathBuf::from("a.log")]);
+    assert_eq!(local.stats().quarantined, 0);
+
+    sink.fail_open("a.log", None);
+    local.write("a.log".into(), WriteData::Append(b"2".to_vec().into()));
+    assert!(local.shutdown().unwrap().failed.is_empty());
+    assert_eq!(sink.read("a.log").unwrap(), b"2");
+}
@@ -0,0 +1,192 @@
+//! 各个测试共用的存储后端包装和辅助函数
+#![allow(dead_code)]
+
+use std::{
+    io::{self, IoSlice},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use write_local::{test_util::TestLocal, MemorySink, Sink, SinkFile, SinkMetadata, WriteData};
+
+/// 追加写入`data`，不推进时钟
+pub fn append(local: &TestLocal, path: &str, data: &[u8]) {
+    local.write(path.into(), WriteData::Append(data.to_vec().into()));
+}
+
+/// 临时目录下本测试专用的空目录
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("write_local-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+type OpenHook<S> = Box<dyn Fn(&S, &Path) -> io::Result<Box<dyn SinkFile>> + Send + Sync>;
+type RenameHook = Box<dyn Fn(&Path, &Path) -> io::Result<()> + Send + Sync>;
+type DirHook = Box<dyn Fn(&Path) + Send + Sync>;
+
+/// 将所有操作转发给`inner`的存储后端，可以替换打开和改名，或在同步目录前插入操作
+pub struct Hooked<S> {
+    inner: S,
+    open: Option<OpenHook<S>>,
+    rename: Option<RenameHook>,
+    sync_dir: Option<DirHook>,
+}
+
+impl<S: Sink> Hooked<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            open: None,
+            rename: None,
+            sync_dir: None,
+        }
+    }
+
+    /// 以追加方式打开文件时改为调用`hook`，可以用`inner`打开后包装返回的文件
+    pub fn on_open(
+        mut self,
+        hook: impl Fn(&S, &Path) -> io::Result<Box<dyn SinkFile>> + Send + Sync + 'static,
+    ) -> Self {
+        self.open = Some(Box::new(hook));
+        self
+    }
+
+    /// 改名时改为调用`hook`
+    pub fn on_rename(
+        mut self,
+        hook: impl Fn(&Path, &Path) -> io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.rename = Some(Box::new(hook));
+        self
+    }
+
+    /// 同步目录前先调用`hook`
+    pub fn on_sync_dir(mut self, hook: impl Fn(&Path) + Send + Sync + 'static) -> Self {
+        self.sync_dir = Some(Box::new(hook));
+        self
+    }
+}
+
+impl<S: Sink> Sink for Hooked<S> {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        match &self.open {
+            Some(hook) => hook(&self.inner, path),
+            None => self.inner.open(path),
+        }
+    }
+
+    fn open_positional(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        self.inner.open_positional(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn SinkFile>> {
+        self.inner.create(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match &self.rename {
+            Some(hook) => hook(from, to),
+            None => self.inner.rename(from, to),
+        }
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<SinkMetadata> {
+        self.inner.metadata(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        if let Some(hook) = &self.sync_dir {
+            hook(dir);
+        }
+        self.inner.sync_dir(dir)
+    }
+
+    fn copy_attributes(
+        &self,
+        from: &Path,
+        to: &Path,
+        permissions: bool,
+        owner: bool,
+    ) -> io::Result<()> {
+        self.inner.copy_attributes(from, to, permissions, owner)
+    }
+}
+
+/// 打开`boom`时panic的存储后端
+pub fn panic_on_open(inner: MemorySink, boom: &'static str) -> Hooked<MemorySink> {
+    Hooked::new(inner).on_open(move |inner, path| {
+        if path == Path::new(boom) {
+            panic!("open {path:?}");
+        }
+        inner.open(path)
+    })
+}
+
+/// 第一次追加时只写入前`short`个字节，下一次追加返回错误，之后恢复正常的存储后端。
+/// 返回的Vec记录每次追加时要求写入的字节数
+pub fn short_write(
+    inner: MemorySink,
+    short: usize,
+) -> (Hooked<MemorySink>, Arc<Mutex<Vec<usize>>>) {
+    let short = Arc::new(Mutex::new(Some(short)));
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let record = requested.clone();
+    let sink = Hooked::new(inner).on_open(move |inner, path| {
+        Ok(Box::new(ShortWriteFile {
+            inner: inner.open(path)?,
+            short: short.clone(),
+            requested: record.clone(),
+            fail_next: false,
+        }))
+    });
+    (sink, requested)
+}
+
+struct ShortWriteFile {
+    inner: Box<dyn SinkFile>,
+    short: Arc<Mutex<Option<usize>>>,
+    requested: Arc<Mutex<Vec<usize>>>,
+    fail_next: bool,
+}
+
+impl SinkFile for ShortWriteFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.append_vectored(&[IoSlice::new(data)])
+    }
+
+    fn append_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let data: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        self.requested.lock().unwrap().push(data.len());
+        if std::mem::take(&mut self.fail_next) {
+            return Err(io::Error::other("disk full"));
+        }
+        match self.short.lock().unwrap().take() {
+            Some(short) => {
+                self.fail_next = true;
+                self.inner.append(&data[..short])
+            }
+            None => self.inner.append(&data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        self.inner.sync(data_only)
+    }
+
+    fn metadata(&self) -> io::Result<SinkMetadata> {
+        self.inner.metadata()
+    }
+}
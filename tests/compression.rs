@@ -0,0 +1,93 @@
+#![cfg(feature = "gzip")]
+
+mod common;
+
+use common::short_write;
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use std::{io::Read, time::Duration};
+use write_local::{
+    test_util::TestLocal, Compression, MemorySink, RetryPolicy, WriteData, WriteLocal,
+};
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    MultiGzDecoder::new(data).read_to_end(&mut decoded).unwrap();
+    decoded
+}
+
+/// 只解压第一个gzip member
+fn gunzip_first(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decoded).unwrap();
+    decoded
+}
+
+fn gzip_local() -> TestLocal {
+    let local = TestLocal::new();
+    local.set_compression("a.gz".into(), Compression::Gzip(6));
+    local
+}
+
+#[test]
+fn each_batch_is_a_separate_member() {
+    let local = gzip_local();
+    local.write("a.gz".into(), WriteData::Append(b"hello ".to_vec().into()));
+    local.advance(Duration::from_millis(100));
+    let first = local.read("a.gz").unwrap().len();
+    local.write("a.gz".into(), WriteData::Append(b"wor".to_vec().into()));
+    local.write("a.gz".into(), WriteData::Append(b"ld".to_vec().into()));
+    local.advance(Duration::from_millis(100));
+
+    // 拼接起来的多个member仍是合法的gzip文件，同一轮合并的数据压缩为一个member
+    let file = local.read("a.gz").unwrap();
+    assert_eq!(gunzip(&file), b"hello world");
+    assert_eq!(gunzip_first(&file), b"hello ");
+    assert_eq!(gunzip_first(&file[first..]), b"world");
+}
+
+#[test]
+fn override_is_compressed_whole() {
+    let local = gzip_local();
+    local.write("a.gz".into(), WriteData::Append(b"old".to_vec().into()));
+    local.advance(Duration::from_millis(100));
+
+    local.write("a.gz".into(), WriteData::Override(b"new ".to_vec().into()));
+    local.write("a.gz".into(), WriteData::Append(b"content".to_vec().into()));
+    local.advance(Duration::from_millis(100));
+
+    // 覆盖写入及合并到其后的追加数据一起压缩为一个member，替换原有的内容
+    let file = local.read("a.gz").unwrap();
+    assert_eq!(gunzip_first(&file), b"new content");
+    assert_eq!(gunzip(&file), b"new content");
+}
+
+#[test]
+fn partially_written_member_is_resumed() {
+    let sink = MemorySink::default();
+    let (short, requested) = short_write(sink.clone(), 16);
+    let local = WriteLocal::builder()
+        .sink(short)
+        // 不自动重试，由第二次flush重试
+        .retry_policy(
+            RetryPolicy::default().backoff(Duration::from_secs(3600), Duration::from_secs(3600)),
+        )
+        .build()
+        .unwrap();
+    local.set_compression("a.gz".into(), Compression::Gzip(6));
+    let data: Vec<u8> = (0..4096u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+    local.write("a.gz".into(), WriteData::Append(data.clone().into()));
+    let summary = local.flush().unwrap();
+    assert_eq!(summary.failed.len(), 1);
+    let half = sink.read("a.gz").unwrap();
+    assert_eq!(half.len(), 16);
+
+    // 重试时接着写入压缩后数据中剩下的部分，不会重新压缩
+    let summary = local.flush().unwrap();
+    assert!(summary.failed.is_empty());
+    let file = sink.read("a.gz").unwrap();
+    assert!(file.starts_with(&half));
+    assert_eq!(gunzip_first(&file), data);
+    assert_eq!(gunzip(&file), data);
+    let requested = requested.lock().unwrap();
+    assert_eq!(requested[1..], [requested[0] - 16, requested[0] - 16]);
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::Hooked;
+use std::{
+    io::{self, IoSlice},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use write_local::{Durability, MemorySink, Sink, SinkFile, SinkMetadata, WriteData, WriteLocal};
+
+type Calls = Arc<Mutex<Vec<(PathBuf, &'static str)>>>;
+
+/// 记录每次flush和sync调用的文件
+struct CountingSyncFile {
+    inner: Box<dyn SinkFile>,
+    path: PathBuf,
+    calls: Calls,
+}
+
+impl SinkFile for CountingSyncFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.inner.append(data)
+    }
+
+    fn append_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.append_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push((self.path.clone(), "flush"));
+        self.inner.flush()
+    }
+
+    fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        let call = match data_only {
+            true => "fdatasync",
+            false => "fsync",
+        };
+        self.calls.lock().unwrap().push((self.path.clone(), call));
+        self.inner.sync(data_only)
+    }
+
+    fn metadata(&self) -> io::Result<SinkMetadata> {
+        self.inner.metadata()
+    }
+}
+
+/// 记录每次flush、sync和sync_dir调用，只在flush时写入，多次写入合并为一轮
+fn counting_local(durability: Durability) -> (WriteLocal, MemorySink, Calls) {
+    let sink = MemorySink::default();
+    let calls = Calls::default();
+    let (files, dirs) = (calls.clone(), calls.clone());
+    let counting = Hooked::new(sink.clone())
+        .on_open(move |inner, path| {
+            Ok(Box::new(CountingSyncFile {
+                inner: inner.open(path)?,
+                path: path.to_path_buf(),
+                calls: files.clone(),
+            }))
+        })
+        .on_sync_dir(move |dir| dirs.lock().unwrap().push((dir.to_path_buf(), "sync_dir")));
+    let local = WriteLocal::builder()
+        .sink(counting)
+        .durability(durability)
+        .batch_window(Duration::from_secs(3600))
+        .build()
+        .unwrap();
+    (local, sink, calls)
+}
+
+fn sorted(calls: &Calls) -> Vec<(PathBuf, &'static str)> {
+    let mut calls = std::mem::take(&mut *calls.lock().unwrap());
+    calls.sort();
+    calls
+}
+
+#[test]
+fn one_sync_per_path_per_round() {
+    let (local, sink, calls) = counting_local(Durability::Fsync);
+    for data in [b"1", b"2", b"3"] {
+        local.write("a.log".into(), WriteData::Append(data.to_vec().into()));
+    }
+    for data in [b"1", b"2"] {
+        local.write("b.log".into(), WriteData::Append(data.to_vec().into()));
+    }
+    local.flush().unwrap();
+    assert_eq!(sink.read("a.log").unwrap(), b"123");
+    assert_eq!(
+        sorted(&calls),
+        [("a.log".into(), "fsync"), ("b.log".into(), "fsync")]
+    );
+
+    // 下一轮再同步一次，没有写入的文件不同步
+    local.write("a.log".into(), WriteData::Append(b"4".to_vec().into()));
+    local.write("a.log".into(), WriteData::Append(b"5".to_vec().into()));
+    local.flush().unwrap();
+    assert_eq!(sorted(&calls), [("a.log".into(), "fsync")]);
+}
+
+#[test]
+fn each_durability_level_syncs_accordingly() {
+    let (local, _sink, calls) = counting_local(Durability::None);
+    let levels = [
+        ("none.log", Durability::None),
+        ("flush.log", Durability::FlushOnly),
+        ("data.log", Durability::Fdatasync),
+        ("fsync.log", Durability::Fsync),
+        ("dir/fsync_dir.log", Durability::FsyncDir),
+    ];
+    for (path, durability) in levels {
+        local.set_durability(path.into(), durability);
+        local.write(path.into(), WriteData::Append(b"1".to_vec().into()));
+        local.write(path.into(), WriteData::Append(b"2".to_vec().into()));
+    }
+    local.flush().unwrap();
+    assert_eq!(
+        sorted(&calls),
+        [
+            ("data.log".into(), "fdatasync"),
+            ("dir".into(), "sync_dir"),
+            ("dir/fsync_dir.log".into(), "fsync"),
+            ("flush.log".into(), "flush"),
+            ("fsync.log".into(), "fsync"),
+        ]
+    );
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::panic_on_open;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use write_local::{MemorySink, WriteData, WriteError, WriteLocal};
+
+#[test]
+fn flush_path_waits_for_write() {
+    let sink = MemorySink::default();
+    let local = WriteLocal::builder()
+        .sink(sink.clone())
+        .batch_window(Duration::from_secs(3600))
+        .build()
+        .unwrap();
+    local.write("a.json".into(), WriteData::Override(b"{}".to_vec().into()));
+    local.write("b.log".into(), WriteData::Append(b"x".to_vec().into()));
+
+    let summary = local.flush_path(Path::new("a.json")).unwrap();
+    assert_eq!(summary.written, vec![(PathBuf::from("a.json"), 2)]);
+    assert_eq!(sink.read("a.json").unwrap(), b"{}");
+    // 同一轮中的其它文件也已写入，只是不在返回的结果中
+    assert_eq!(sink.read("b.log").unwrap(), b"x");
+}
+
+#[test]
+fn flush_returns_none_after_worker_panics() {
+    let local = WriteLocal::builder()
+        .sink(panic_on_open(MemorySink::default(), "boom.log"))
+        .build()
+        .unwrap();
+    let ack = local.write_with_ack("boom.log".into(), WriteData::Append(b"1".to_vec().into()));
+    assert!(local.flush().is_none());
+    assert!(matches!(ack.wait(), Err(WriteError::Closed)));
+
+    // 之后的flush和写入也不会阻塞
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"2".to_vec().into()));
+    assert!(local.flush().is_none());
+    assert!(matches!(ack.wait(), Err(WriteError::Closed)));
+    assert!(local.shutdown().is_none());
+}
+
+#[test]
+fn flush_returns_none_when_one_worker_panics() {
+    let sink = MemorySink::default();
+    let local = WriteLocal::builder()
+        .sink(panic_on_open(sink.clone(), "boom.log"))
+        .worker_threads(8)
+        .batch_window(Duration::from_secs(3600))
+        .build()
+        .unwrap();
+    let paths: Vec<PathBuf> = (0..16).map(|f| format!("{f}.log").into()).collect();
+    for path in &paths {
+        local.write(path.clone(), WriteData::Append(b"1".to_vec().into()));
+    }
+    local.write("boom.log".into(), WriteData::Append(b"1".to_vec().into()));
+
+    // 其它线程仍然写完了，但不能保证所有数据都已写入
+    assert!(local.flush().is_none());
+    assert!(paths.iter().any(|path| sink.read(path).is_some()));
+    assert!(local.flush().is_none());
+    assert!(local.shutdown().is_none());
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::test_dir;
+use std::{path::Path, time::Duration};
+use write_local::{test_util::TestLocal, Sink, WriteData, WriteLocal};
+
+/// 追加写入`data`，并推进时钟完成这一轮写入
+fn append(local: &TestLocal, path: &str, data: &[u8]) {
+    common::append(local, path, data);
+    local.advance(Duration::from_millis(100));
+}
+
+#[test]
+fn least_recently_used_handle_is_closed() {
+    let local = TestLocal::with_builder(WriteLocal::builder().max_open_files(2));
+    append(&local, "a.log", b"1");
+    append(&local, "b.log", b"1");
+    append(&local, "a.log", b"2");
+    // 缓存已满，关闭最久未使用的b.log
+    append(&local, "c.log", b"1");
+    append(&local, "a.log", b"3");
+    assert_eq!(local.sink().opens("a.log"), 1);
+    append(&local, "b.log", b"2");
+    assert_eq!(local.sink().opens("b.log"), 2);
+    assert_eq!(local.sink().opens("c.log"), 1);
+    assert_eq!(local.read("a.log").unwrap(), b"123");
+    assert_eq!(local.read("b.log").unwrap(), b"12");
+}
+
+/// 两个后台写线程时，由同一个线程负责的两个文件
+fn paths_on_same_worker() -> (String, String) {
+    let local = TestLocal::with_builder(WriteLocal::builder().worker_threads(2));
+    for i in 1.. {
+        let other = format!("{i}.log");
+        common::append(&local, "0.log", b"1");
+        common::append(&local, &other, b"1");
+        // 只有负责0.log的线程写入
+        local.flush_path(Path::new("0.log")).unwrap();
+        if local.read(&other).is_some() {
+            return ("0.log".into(), other);
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn open_file_limit_is_split_between_workers() {
+    let (a, b) = paths_on_same_worker();
+    let local = TestLocal::with_builder(WriteLocal::builder().worker_threads(2).max_open_files(2));
+    // 每个线程只缓存一个句柄
+    append(&local, &a, b"1");
+    append(&local, &b, b"1");
+    append(&local, &a, b"2");
+    assert_eq!(local.sink().opens(&a), 2);
+    assert_eq!(local.read(&a).unwrap(), b"12");
+}
+
+#[test]
+fn idle_handle_is_closed() {
+    let local = TestLocal::with_builder(
+        WriteLocal::builder()
+            .idle_file_timeout(Duration::from_secs(1))
+            .idle_path_timeout(Duration::from_secs(3600)),
+    );
+    append(&local, "a.log", b"1");
+    local.advance(Duration::from_millis(899));
+    append(&local, "a.log", b"2");
+    assert_eq!(local.sink().opens("a.log"), 1);
+
+    local.advance(Duration::from_secs(1));
+    append(&local, "a.log", b"3");
+    assert_eq!(local.sink().opens("a.log"), 2);
+    assert_eq!(local.read("a.log").unwrap(), b"123");
+}
+
+#[test]
+fn renamed_or_removed_file_is_reopened() {
+    let local = TestLocal::new();
+    let sink = local.sink();
+    append(&local, "a.log", b"1");
+
+    // 两次写入之间被轮转，之后的数据写入新建的同名文件
+    sink.rename(Path::new("a.log"), Path::new("a.log.1"))
+        .unwrap();
+    append(&local, "a.log", b"2");
+    assert_eq!(local.read("a.log.1").unwrap(), b"1");
+    assert_eq!(local.read("a.log").unwrap(), b"2");
+
+    sink.remove(Path::new("a.log")).unwrap();
+    append(&local, "a.log", b"3");
+    assert_eq!(local.read("a.log").unwrap(), b"3");
+    assert_eq!(sink.opens("a.log"), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn renamed_file_on_disk_is_reopened() {
+    let dir = test_dir("reopen");
+    let path = dir.join("a.log");
+    let rotated = dir.join("a.log.1");
+    let local = WriteLocal::init();
+
+    local.write(path.clone(), WriteData::Append(b"1".to_vec().into()));
+    local.flush().unwrap();
+    std::fs::rename(&path, &rotated).unwrap();
+    local.write(path.clone(), WriteData::Append(b"2".to_vec().into()));
+    local.flush().unwrap();
+    assert_eq!(std::fs::read(&rotated).unwrap(), b"1");
+    assert_eq!(std::fs::read(&path).unwrap(), b"2");
+
+    local.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
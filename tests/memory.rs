@@ -0,0 +1,85 @@
+mod common;
+
+use common::append;
+use std::{
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use write_local::{test_util::TestLocal, DeadLetter, WriteData, WriteError, WriteLocal};
+
+#[test]
+fn idle_paths_are_evicted() {
+    let local =
+        TestLocal::with_builder(WriteLocal::builder().idle_path_timeout(Duration::from_secs(1)));
+    local
+        .sink()
+        .fail_open("bad.log", Some(io::ErrorKind::StorageFull));
+    append(&local, "a.log", b"1");
+    append(&local, "b.log", b"1");
+    append(&local, "bad.log", b"1");
+    local.advance(Duration::from_millis(100));
+    assert_eq!(local.memory_stats().cached_paths, 3);
+    assert_eq!(local.stats().paths.len(), 3);
+
+    local.advance(Duration::from_millis(400));
+    append(&local, "b.log", b"2");
+    local.advance(Duration::from_millis(400));
+    assert_eq!(local.memory_stats().cached_paths, 3);
+
+    // a.log闲置超时被移除，b.log刚写过，bad.log还有等待重试的数据，都不会被移除
+    local.advance(Duration::from_millis(200));
+    let memory = local.memory_stats();
+    assert_eq!(memory.cached_paths, 2);
+    assert_eq!(memory.pending_bytes, 1);
+    let stats = local.stats();
+    assert!(!stats.paths.contains_key(Path::new("a.log")));
+    assert_eq!(stats.paths.len(), 2);
+
+    // 重试写入成功后，bad.log从写入时开始计算闲置时间
+    local.sink().fail_open("bad.log", None);
+    local.advance(Duration::from_secs(2));
+    assert_eq!(local.read("bad.log").unwrap(), b"1");
+    assert_eq!(local.memory_stats().cached_paths, 1);
+    local.advance(Duration::from_secs(1));
+    assert_eq!(local.memory_stats().cached_paths, 0);
+    assert_eq!(local.memory_stats().pending_bytes, 0);
+    assert!(local.stats().paths.is_empty());
+}
+
+#[test]
+fn pending_budget_gives_up_failing_path() {
+    let dead = Arc::new(Mutex::new(Vec::<DeadLetter>::new()));
+    let dead_letters = dead.clone();
+    let local = TestLocal::with_builder(
+        WriteLocal::builder()
+            .max_pending_bytes(10)
+            .dead_letter(move |letter| dead_letters.lock().unwrap().push(letter)),
+    );
+    local
+        .sink()
+        .fail_open("bad.log", Some(io::ErrorKind::StorageFull));
+    let first = local.write_with_ack("bad.log".into(), WriteData::Append(b"1234".to_vec().into()));
+    append(&local, "ok.log", b"ok");
+    local.advance(Duration::from_millis(100));
+    assert_eq!(local.memory_stats().pending_bytes, 4);
+    assert!(first.try_wait().is_none());
+
+    // 超出上限时立即写入，写入后仍超出上限，放弃写入失败的数据
+    append(&local, "ok.log", b"!!!!");
+    append(&local, "bad.log", b"56789abc");
+    local.advance(Duration::ZERO);
+    assert_eq!(local.trigger_counts().memory_budget, 1);
+    assert!(matches!(first.try_wait(), Some(Err(WriteError::Io { .. }))));
+    let memory = local.memory_stats();
+    assert_eq!(memory.pending_bytes, 0);
+    assert_eq!(memory.cached_paths, 2);
+    assert_eq!(local.stats().given_up, 1);
+    assert_eq!(local.read("ok.log").unwrap(), b"ok!!!!");
+
+    let dead = dead.lock().unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].path.to_str(), Some("bad.log"));
+    assert!(matches!(&dead[0].data, WriteData::Append(data) if data.to_vec() == b"123456789abc"));
+}
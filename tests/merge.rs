@@ -0,0 +1,212 @@
+mod common;
+
+use common::test_dir;
+use std::sync::Arc;
+use write_local::{Payload, WriteData, WriteLocal};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Append,
+    Override,
+    /// 空的覆盖写入，清空文件
+    Truncate,
+}
+
+/// 所有长度不超过3的追加/覆盖/清空组合
+fn interleavings() -> Vec<Vec<Kind>> {
+    let kinds = [Kind::Append, Kind::Override, Kind::Truncate];
+    let mut all = Vec::new();
+    for len in 1..=3 {
+        for mut n in 0..kinds.len().pow(len) {
+            all.push(
+                (0..len)
+                    .map(|_| {
+                        let kind = kinds[n % kinds.len()];
+                        n /= kinds.len();
+                        kind
+                    })
+                    .collect(),
+            );
+        }
+    }
+    all
+}
+
+/// 依次写入这些数据后，文件应有的内容
+fn expected(initial: &[u8], ops: &[(bool, Vec<u8>)]) -> Vec<u8> {
+    let mut content = initial.to_vec();
+    for (is_override, data) in ops {
+        if *is_override {
+            content.clear();
+        }
+        content.extend(data);
+    }
+    content
+}
+
+fn check(name: &str, flush_each: bool) {
+    let dir = test_dir(name);
+    let writer = WriteLocal::init();
+
+    for (i, kinds) in interleavings().into_iter().enumerate() {
+        let path = dir.join(format!("{i}.txt"));
+        std::fs::write(&path, b"init|").unwrap();
+
+        let ops: Vec<_> = kinds
+            .iter()
+            .enumerate()
+            .map(|(j, &kind)| match kind {
+                Kind::Truncate => (true, Vec::new()),
+                _ => (
+                    kind == Kind::Override,
+                    format!("{j}:{kind:?}|").into_bytes(),
+                ),
+            })
+            .collect();
+        for (is_override, data) in &ops {
+            let data = if *is_override {
+                WriteData::Override(data.clone().into())
+            } else {
+                WriteData::Append(data.clone().into())
+            };
+            writer.write(path.clone(), data);
+            if flush_each {
+                writer.flush_path(&path).unwrap();
+            }
+        }
+        writer.flush_path(&path).unwrap();
+
+        let content = std::fs::read(&path).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&content),
+            String::from_utf8_lossy(&expected(b"init|", &ops)),
+            "{kinds:?}"
+        );
+    }
+
+    writer.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn mixed_writes_in_one_batch() {
+    check("one-batch", false);
+}
+
+#[test]
+fn mixed_writes_across_batches() {
+    check("across-batches", true);
+}
+
+#[test]
+fn append_after_override_batch_is_appended() {
+    let dir = test_dir("append-after-override");
+    let path = dir.join("a.txt");
+    let writer = WriteLocal::init();
+
+    writer.write(path.clone(), WriteData::Override(b"A".to_vec().into()));
+    writer.flush().unwrap();
+    writer.write(path.clone(), WriteData::Append(b"B".to_vec().into()));
+    writer.flush().unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"AB");
+    writer.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn shared_payloads_are_merged_in_order() {
+    let dir = test_dir("shared-payloads");
+    let path = dir.join("a.txt");
+    let writer = WriteLocal::init();
+
+    let large: Arc<[u8]> = vec![b'x'; 10000].into();
+    writer.write(path.clone(), WriteData::Append(b"head".to_vec().into()));
+    writer.write(path.clone(), WriteData::Append(large.clone().into()));
+    writer.write(path.clone(), WriteData::Append((&b"static"[..]).into()));
+    let mut payload = Payload::from(vec![b'y'; 5000]);
+    payload.push(&b"tail"[..]);
+    writer.write(path.clone(), WriteData::Append(payload));
+    writer.flush().unwrap();
+
+    let mut expected = b"head".to_vec();
+    expected.extend_from_slice(&large);
+    expected.extend_from_slice(b"static");
+    expected.extend(vec![b'y'; 5000]);
+    expected.extend_from_slice(b"tail");
+    assert_eq!(std::fs::read(&path).unwrap(), expected);
+    writer.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn overlapping_write_at_last_writer_wins() {
+    let dir = test_dir("write-at-overlap");
+    let path = dir.join("index.bin");
+    std::fs::write(&path, b"0123456789").unwrap();
+    let writer = WriteLocal::init();
+
+    let write_at = |offset, data: &[u8]| WriteData::WriteAt {
+        offset,
+        data: data.to_vec().into(),
+    };
+    writer.write(path.clone(), write_at(2, b"ab"));
+    writer.write(path.clone(), write_at(3, b"XYZ"));
+    writer.write(path.clone(), write_at(1, b"-"));
+    // 超出文件末尾时扩展文件，中间的空洞为0
+    writer.write(path.clone(), write_at(12, b"!!"));
+    writer.flush().unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"0-aXYZ6789\0\0!!");
+    writer.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn write_at_interleaved_with_other_writes_keeps_order() {
+    let dir = test_dir("write-at-interleaved");
+    let writer = WriteLocal::init();
+
+    let appended = dir.join("appended.txt");
+    std::fs::write(&appended, b"init|").unwrap();
+    let acks = vec![
+        writer.write_with_ack(appended.clone(), WriteData::Append(b"A|".to_vec().into())),
+        writer.write_with_ack(
+            appended.clone(),
+            WriteData::WriteAt {
+                offset: 0,
+                data: b"I".to_vec().into(),
+            },
+        ),
+        writer.write_with_ack(appended.clone(), WriteData::Append(b"B|".to_vec().into())),
+        writer.write_with_ack(
+            appended.clone(),
+            WriteData::WriteAt {
+                offset: 1,
+                data: b"N".to_vec().into(),
+            },
+        ),
+    ];
+
+    let overridden = dir.join("overridden.txt");
+    writer.write(
+        overridden.clone(),
+        WriteData::Override(b"hello".to_vec().into()),
+    );
+    writer.write(
+        overridden.clone(),
+        WriteData::WriteAt {
+            offset: 0,
+            data: b"J".to_vec().into(),
+        },
+    );
+    writer.flush().unwrap();
+
+    for ack in acks {
+        ack.wait().unwrap();
+    }
+    assert_eq!(std::fs::read(&appended).unwrap(), b"INit|A|B|");
+    assert_eq!(std::fs::read(&overridden).unwrap(), b"Jello");
+    writer.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
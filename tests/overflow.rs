@@ -0,0 +1,222 @@
+mod common;
+
+use common::Hooked;
+use flume::Sender;
+use std::{path::Path, time::Duration};
+use write_local::{
+    Durability, MemorySink, OverflowPolicy, RotationPolicy, Sink, SubmitError, WriteData,
+    WriteError, WriteLocal,
+};
+
+/// channel容量为1、后台写线程被阻塞在`gate.log`上的WriteLocal，返回后channel是空的
+fn blocked(policy: OverflowPolicy) -> (WriteLocal, MemorySink, Sender<()>) {
+    blocked_with(MemorySink::default(), policy, 1)
+}
+
+/// 同[`blocked`]，使用已有的`sink`和指定的channel容量
+fn blocked_with(
+    sink: MemorySink,
+    policy: OverflowPolicy,
+    capacity: usize,
+) -> (WriteLocal, MemorySink, Sender<()>) {
+    let (entered, entered_rx) = flume::unbounded();
+    let (release, release_rx) = flume::unbounded();
+    // 打开`gate.log`时阻塞，直到测试放行，期间后台写线程不再从channel中取消息
+    let gate = Hooked::new(sink.clone()).on_open(move |inner, path| {
+        if path == Path::new("gate.log") {
+            let _ = entered.send(());
+            let _ = release_rx.recv();
+        }
+        inner.open(path)
+    });
+    let local = WriteLocal::builder()
+        .sink(gate)
+        .channel_capacity(capacity)
+        .max_batch_count(1)
+        .overflow_policy(policy)
+        .build()
+        .unwrap();
+    local.write("gate.log".into(), append(b"g"));
+    entered_rx.recv().unwrap();
+    (local, sink, release)
+}
+
+fn append(data: &[u8]) -> WriteData {
+    WriteData::Append(data.to_vec().into())
+}
+
+#[test]
+fn try_write_returns_data_when_full() {
+    let (local, sink, release) = blocked(OverflowPolicy::Block);
+    local.try_write("a.log".into(), append(b"a")).unwrap();
+    match local.try_write("b.log".into(), append(b"b")) {
+        Err(SubmitError::Full(path, data)) => {
+            assert_eq!(path.to_str(), Some("b.log"));
+            assert!(matches!(data, WriteData::Append(data) if data.to_vec() == b"b"));
+        }
+        _ => panic!("channel should be full"),
+    }
+    let res = local.write_timeout("b.log".into(), append(b"b"), Duration::from_millis(10));
+    assert!(matches!(res, Err(SubmitError::Timeout(..))));
+
+    release.send(()).unwrap();
+    local.flush();
+    assert_eq!(sink.read("a.log").unwrap(), b"a");
+    assert_eq!(sink.read("b.log"), None);
+}
+
+#[test]
+fn drop_newest_rejects_new_data() {
+    let (local, sink, release) = blocked(OverflowPolicy::DropNewest);
+    let kept = local.write_with_ack("a.log".into(), append(b"a"));
+    let dropped = local.write_with_ack("b.log".into(), append(b"b"));
+    assert!(matches!(dropped.wait(), Err(WriteError::Dropped)));
+    assert_eq!(local.overflow_counts().dropped_newest, 1);
+
+    release.send(()).unwrap();
+    local.flush();
+    kept.wait().unwrap();
+    assert_eq!(sink.read("a.log").unwrap(), b"a");
+    assert_eq!(sink.read("b.log"), None);
+}
+
+#[test]
+fn drop_oldest_makes_room_for_new_data() {
+    let (local, sink, release) = blocked(OverflowPolicy::DropOldest);
+    let dropped = local.write_with_ack("a.log".into(), append(b"a"));
+    let kept = local.write_with_ack("b.log".into(), append(b"b"));
+    assert!(matches!(dropped.wait(), Err(WriteError::Dropped)));
+    assert_eq!(local.overflow_counts().dropped_oldest, 1);
+
+    release.send(()).unwrap();
+    local.flush();
+    kept.wait().unwrap();
+    assert_eq!(sink.read("a.log"), None);
+    assert_eq!(sink.read("b.log").unwrap(), b"b");
+}
+
+#[test]
+fn drop_oldest_keeps_settings_in_place() {
+    let sink = MemorySink::default();
+    sink.create(Path::new("a.log"))
+        .unwrap()
+        .append(b"0")
+        .unwrap();
+    let (local, sink, release) = blocked_with(sink, OverflowPolicy::DropOldest, 3);
+    let policy = RotationPolicy::default().max_size(1);
+    local.set_rotation("a.log".into(), Some(policy));
+    local.write("a.log".into(), append(b"1"));
+    local.write("a.log".into(), append(b"2"));
+    // 丢弃的是最早的数据"1"，轮转设置仍然在"2"之前，写入"2"前就已经生效
+    local.write("a.log".into(), append(b"3"));
+    assert_eq!(local.overflow_counts().dropped_oldest, 1);
+
+    release.send(()).unwrap();
+    local.flush();
+    assert_eq!(sink.read("a.log").unwrap(), b"3");
+    let mut rotated: Vec<_> = sink
+        .paths()
+        .into_iter()
+        .filter(|path| path.to_str().is_some_and(|path| path.starts_with("a.log.")))
+        .map(|path| sink.read(path).unwrap())
+        .collect();
+    rotated.sort();
+    assert_eq!(rotated, [b"0", b"2"]);
+}
+
+#[test]
+fn drop_oldest_control_messages_wait_for_room() {
+    let (local, _, release) = blocked(OverflowPolicy::DropOldest);
+    local.set_durability("b.log".into(), Durability::FlushOnly);
+
+    // flush、设置等消息不丢弃数据，channel已满时阻塞等待后台写线程取走设置
+    let flushing = {
+        let local = local.clone();
+        std::thread::spawn(move || local.flush())
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!flushing.is_finished());
+    release.send(()).unwrap();
+    assert!(flushing.join().unwrap().is_some());
+    assert_eq!(local.overflow_counts().dropped_oldest, 0);
+}
+
+#[test]
+fn spill_writes_overflow_to_disk() {
+    let dir = std::env::temp_dir().join(format!("write_local-{}-overflow", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (local, sink, release) = blocked(OverflowPolicy::Spill(dir.clone()));
+    let kept = local.write_with_ack("a.log".into(), append(b"a"));
+    let spilled = local.write_with_ack("b.log".into(), append(b"b"));
+    match spilled.wait() {
+        Err(WriteError::Spilled { path, spill_file }) => {
+            assert_eq!(path.to_str(), Some("b.log"));
+            assert_eq!(std::fs::read(spill_file).unwrap(), b"b");
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(local.overflow_counts().spilled, 1);
+
+    release.send(()).unwrap();
+    kept.wait().unwrap();
+    let report = local.replay_spill().unwrap();
+    assert_eq!(report.replayed, 1);
+    assert_eq!(sink.read("b.log").unwrap(), b"b");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// 不断poll`future`直到完成，返回其结果和poll的次数
+#[cfg(feature = "async")]
+fn poll_until_ready<F: std::future::Future>(future: F) -> (F::Output, usize) {
+    use std::task::{Context, Poll, Waker};
+
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut future = std::pin::pin!(future);
+    let mut polls = 1;
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return (output, polls);
+        }
+        std::thread::sleep(Duration::from_millis(1));
+        polls += 1;
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_overflow_does_not_block() {
+    let (local, sink, release) = blocked(OverflowPolicy::DropNewest);
+    local.write("a.log".into(), append(b"a"));
+    let (ack, polls) = poll_until_ready(local.write_with_ack_async("b.log".into(), append(b"b")));
+    assert_eq!(polls, 1);
+    assert!(matches!(ack.wait(), Err(WriteError::Dropped)));
+
+    release.send(()).unwrap();
+    local.flush();
+    assert_eq!(sink.read("a.log").unwrap(), b"a");
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_drop_oldest_suspends_behind_settings() {
+    let (local, sink, release) = blocked(OverflowPolicy::DropOldest);
+    local.set_durability("a.log".into(), Durability::FlushOnly);
+
+    // channel中只有设置时无法丢弃数据，挂起等待而不是阻塞线程
+    let writing = {
+        let local = local.clone();
+        std::thread::spawn(move || {
+            poll_until_ready(local.write_async("a.log".into(), append(b"a"))).1
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!writing.is_finished());
+    release.send(()).unwrap();
+    assert!(writing.join().unwrap() > 1);
+
+    // 挂起等待的数据在channel有空位后写入，没有丢弃任何数据
+    local.flush();
+    assert_eq!(sink.read("a.log").unwrap(), b"a");
+    assert_eq!(local.overflow_counts().dropped_oldest, 0);
+}
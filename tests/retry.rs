@@ -0,0 +1,127 @@
+mod common;
+
+use common::short_write;
+use std::{
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use write_local::{
+    test_util::TestLocal, DeadLetter, MemorySink, RetryPolicy, WriteData, WriteError, WriteLocal,
+};
+
+/// 使用`policy`重试，放弃的数据收集到返回的Vec中
+fn retrying_local(policy: RetryPolicy) -> (TestLocal, Arc<Mutex<Vec<DeadLetter>>>) {
+    let dead = Arc::new(Mutex::new(Vec::new()));
+    let dead_letters = dead.clone();
+    let local = TestLocal::with_builder(
+        WriteLocal::builder()
+            .retry_policy(policy)
+            .dead_letter(move |letter| dead_letters.lock().unwrap().push(letter)),
+    );
+    (local, dead)
+}
+
+#[test]
+fn retries_follow_backoff() {
+    let policy = RetryPolicy::default()
+        .max_attempts(4)
+        .backoff(Duration::from_millis(100), Duration::from_millis(300));
+    let (local, dead) = retrying_local(policy);
+    local
+        .sink()
+        .fail_open("a.log", Some(io::ErrorKind::StorageFull));
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+    local.advance(Duration::from_millis(100));
+    assert_eq!(local.stats().write_errors, 1);
+
+    // 重试间隔依次为100ms、200ms、300ms(达到上限)，期间调用者一直等待
+    for (attempts, backoff) in [(2, 100), (3, 200)] {
+        local.advance(Duration::from_millis(backoff - 1));
+        assert_eq!(local.stats().write_errors, attempts - 1);
+        assert!(ack.try_wait().is_none());
+        local.advance(Duration::from_millis(1));
+        assert_eq!(local.stats().write_errors, attempts);
+        assert_eq!(local.trigger_counts().retry, attempts - 1);
+    }
+    assert!(ack.try_wait().is_none());
+
+    local.sink().fail_open("a.log", None);
+    local.advance(Duration::from_millis(299));
+    assert_eq!(local.read("a.log"), None);
+    local.advance(Duration::from_millis(1));
+    assert_eq!(local.read("a.log").unwrap(), b"1");
+    assert_eq!(ack.wait().unwrap().bytes, 1);
+    assert!(dead.lock().unwrap().is_empty());
+}
+
+#[test]
+fn exhausted_retries_go_to_dead_letter() {
+    let policy = RetryPolicy::default()
+        .max_attempts(2)
+        .backoff(Duration::from_millis(100), Duration::from_secs(1));
+    let (local, dead) = retrying_local(policy);
+    local
+        .sink()
+        .fail_open("a.log", Some(io::ErrorKind::StorageFull));
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+    local.advance(Duration::from_millis(100));
+    assert!(ack.try_wait().is_none());
+    assert!(dead.lock().unwrap().is_empty());
+
+    local.advance(Duration::from_millis(100));
+    assert!(matches!(ack.wait(), Err(WriteError::Io { .. })));
+    let dead = dead.lock().unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].attempts, 2);
+    assert_eq!(dead[0].error.kind(), io::ErrorKind::StorageFull);
+    assert_eq!(local.stats().given_up, 1);
+}
+
+#[test]
+fn non_retryable_error_goes_to_dead_letter_at_once() {
+    let policy = RetryPolicy::default().retry_if(|e| e.kind() != io::ErrorKind::PermissionDenied);
+    let (local, dead) = retrying_local(policy);
+    local
+        .sink()
+        .fail_open("a.log", Some(io::ErrorKind::PermissionDenied));
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+    local.advance(Duration::from_millis(100));
+
+    match ack.wait() {
+        Err(WriteError::Io { source, .. }) => {
+            assert_eq!(source.kind(), io::ErrorKind::PermissionDenied)
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    let dead = dead.lock().unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].attempts, 1);
+    assert!(matches!(&dead[0].data, WriteData::Append(data) if data.to_vec() == b"1"));
+    assert_eq!(local.stats().write_errors, 1);
+    assert_eq!(local.trigger_counts().retry, 0);
+}
+
+#[test]
+fn retry_writes_only_the_unwritten_tail() {
+    let sink = MemorySink::default();
+    let (short, requested) = short_write(sink.clone(), 4);
+    let local = WriteLocal::builder().sink(short).build().unwrap();
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"01234".to_vec().into()));
+    local.write("a.log".into(), WriteData::Append(b"56789".to_vec().into()));
+    let summary = local.flush().unwrap();
+    assert_eq!(summary.failed.len(), 1);
+    assert!(summary.written.is_empty());
+    assert_eq!(sink.read("a.log").unwrap(), b"0123");
+    assert!(ack.try_wait().is_none());
+
+    // 已写入的4个字节不会重复写入
+    let summary = local.flush().unwrap();
+    assert_eq!(summary.written, [(PathBuf::from("a.log"), 10)]);
+    assert_eq!(sink.read("a.log").unwrap(), b"0123456789");
+    assert_eq!(*requested.lock().unwrap(), [10, 6, 6]);
+    let report = ack.wait().unwrap();
+    assert_eq!(report.bytes, 10);
+    assert_eq!(local.stats().bytes_written, 10);
+}
@@ -0,0 +1,65 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, UNIX_EPOCH},
+};
+use write_local::{test_util::TestLocal, RotationInterval, RotationPolicy, WriteData};
+
+/// 2026-10-15 23:30:00 UTC
+const STAMP: u64 = 1_792_107_000;
+
+fn append(local: &TestLocal, data: &[u8]) {
+    local.write("app.log".into(), WriteData::Append(data.to_vec().into()));
+    local.advance(Duration::from_millis(100));
+}
+
+fn local(policy: RotationPolicy) -> TestLocal {
+    let local = TestLocal::new();
+    local.set_wall_clock(UNIX_EPOCH + Duration::from_secs(STAMP));
+    local.set_rotation("app.log".into(), Some(policy));
+    local
+}
+
+#[test]
+fn size_rotation_uses_writer_clock() {
+    let local = local(RotationPolicy::default().max_size(3));
+    append(&local, b"abc");
+    append(&local, b"de");
+    append(&local, b"f");
+    append(&local, b"gh");
+
+    let paths = ["app.log", "app.log.2026-10-15.1", "app.log.2026-10-15.2"];
+    assert_eq!(local.sink().paths(), paths.map(PathBuf::from));
+    assert_eq!(local.read("app.log.2026-10-15.1").unwrap(), b"abc");
+    assert_eq!(local.read("app.log.2026-10-15.2").unwrap(), b"def");
+    assert_eq!(local.read("app.log").unwrap(), b"gh");
+}
+
+#[test]
+fn daily_rotation_follows_advance() {
+    let local = local(RotationPolicy::default().interval(RotationInterval::Daily));
+    append(&local, b"1");
+    append(&local, b"2");
+    assert_eq!(local.sink().paths(), [PathBuf::from("app.log")]);
+
+    // 推进到第二天后轮转，轮转出去的文件使用数据所属的日期
+    local.advance(Duration::from_secs(1800));
+    append(&local, b"3");
+    assert_eq!(local.read("app.log.2026-10-15.1").unwrap(), b"12");
+    assert_eq!(local.read("app.log").unwrap(), b"3");
+}
+
+#[test]
+fn keep_removes_oldest_rotated_files() {
+    let local = local(RotationPolicy::default().max_size(1).keep(2));
+    for data in [b"1", b"2", b"3", b"4", b"5"] {
+        append(&local, data);
+        // 修改时间的精度可能不足以区分先后轮转出去的文件
+        std::thread::sleep(Duration::from_millis(2));
+    }
+
+    let paths = ["app.log", "app.log.2026-10-15.3", "app.log.2026-10-15.4"];
+    assert_eq!(local.sink().paths(), paths.map(PathBuf::from));
+    assert_eq!(local.read("app.log.2026-10-15.3").unwrap(), b"3");
+    assert_eq!(local.read("app.log.2026-10-15.4").unwrap(), b"4");
+    assert_eq!(local.read("app.log").unwrap(), b"5");
+}
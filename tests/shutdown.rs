@@ -0,0 +1,85 @@
+mod common;
+
+use common::Hooked;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use write_local::{MemorySink, Sink, WriteData, WriteError, WriteLocal};
+
+#[test]
+fn dropping_last_clone_flushes_and_joins() {
+    let sink = MemorySink::default();
+    let local = WriteLocal::builder()
+        // 打开文件前先等一会，没有等待后台线程退出时来不及写入
+        .sink(Hooked::new(sink.clone()).on_open(|inner, path| {
+            thread::sleep(Duration::from_millis(50));
+            inner.open(path)
+        }))
+        .batch_window(Duration::from_secs(3600))
+        .build()
+        .unwrap();
+    let clone = local.clone();
+    clone.write("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+
+    // 还有其它克隆时不会关闭
+    drop(clone);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(sink.read("a.log"), None);
+
+    drop(local);
+    assert_eq!(sink.read("a.log").unwrap(), b"1");
+}
+
+#[test]
+fn only_first_shutdown_returns_summary() {
+    let sink = MemorySink::default();
+    let local = WriteLocal::builder().sink(sink.clone()).build().unwrap();
+    let clone = local.clone();
+    local.write("a.log".into(), WriteData::Append(b"12".to_vec().into()));
+
+    let summary = clone.shutdown().unwrap();
+    assert_eq!(summary.written, [(PathBuf::from("a.log"), 2)]);
+    assert!(local.shutdown().is_none());
+    assert!(clone.shutdown().is_none());
+
+    // 关闭之后发送的数据被丢弃
+    let ack = local.write_with_ack("a.log".into(), WriteData::Append(b"3".to_vec().into()));
+    assert!(matches!(ack.wait(), Err(WriteError::Closed)));
+    assert_eq!(sink.read("a.log").unwrap(), b"12");
+}
+
+#[test]
+fn deferred_data_given_up_on_shutdown_is_reported() {
+    let sink = MemorySink::default();
+    let local = WriteLocal::builder().sink(sink.clone()).build().unwrap();
+    sink.fail_open("a.log", Some(io::ErrorKind::StorageFull));
+    let append = local.write_with_ack("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+    assert_eq!(local.flush().unwrap().failed.len(), 1);
+
+    // 随机写入无法与写入失败的追加数据合并，排队等待，退出时一起放弃
+    let write_at = local.write_with_ack(
+        "a.log".into(),
+        WriteData::WriteAt {
+            offset: 0,
+            data: b"2".to_vec().into(),
+        },
+    );
+    let summary = local.shutdown().unwrap();
+    assert!(summary.written.is_empty());
+    assert_eq!(summary.failed.len(), 2);
+    for (path, error) in &summary.failed {
+        assert_eq!(path, Path::new("a.log"));
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+    }
+    for ack in [append, write_at] {
+        match ack.wait() {
+            Err(WriteError::Io { source, .. }) => {
+                assert_eq!(source.kind(), io::ErrorKind::StorageFull)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
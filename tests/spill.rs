@@ -0,0 +1,256 @@
+mod common;
+
+use common::{test_dir, Hooked};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use write_local::{
+    MemorySink, OverflowPolicy, RetryPolicy, Sink, WriteData, WriteError, WriteLocal,
+};
+
+/// 写入失败后不重试，直接写入溢出目录`dir`
+fn spilling_local(sink: &MemorySink, dir: &Path) -> WriteLocal {
+    WriteLocal::builder()
+        .sink(sink.clone())
+        .spill_dir(dir)
+        .retry_policy(RetryPolicy::never())
+        .batch_window(Duration::from_secs(3600))
+        .build()
+        .unwrap()
+}
+
+/// 溢出目录中的文件名，按名称排序
+fn spill_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn spilled_data_is_replayed_in_order() {
+    let dir = test_dir("spill-order");
+    let sink = MemorySink::default();
+    let local = spilling_local(&sink, &dir);
+    sink.fail_open("a.log", Some(io::ErrorKind::PermissionDenied));
+
+    // 每轮放弃的数据各是一份溢出数据
+    for data in [b"1", b"2", b"3"] {
+        let ack = local.write_with_ack("a.log".into(), WriteData::Append(data.to_vec().into()));
+        local.flush().unwrap();
+        let Err(WriteError::Spilled { path, spill_file }) = ack.wait() else {
+            panic!("data is not spilled");
+        };
+        assert_eq!(path, Path::new("a.log"));
+        assert_eq!(std::fs::read(&spill_file).unwrap(), data);
+    }
+    let names = spill_files(&dir);
+    assert_eq!(names.len(), 6);
+    assert!(names.iter().all(|name| !name.ends_with(".tmp")));
+    let meta = names.iter().find(|name| name.ends_with(".meta")).unwrap();
+    let meta = std::fs::read_to_string(dir.join(meta)).unwrap();
+    assert!(meta.starts_with("mode=append\n"));
+    assert!(meta.contains("\nerror=injected failure"));
+    assert!(meta.ends_with("\npath=a.log"));
+
+    sink.fail_open("a.log", None);
+    let report = local.replay_spill().unwrap();
+    assert_eq!(report.replayed, 3);
+    assert!(report.failed.is_empty());
+    assert_eq!(sink.read("a.log").unwrap(), b"123");
+    assert!(spill_files(&dir).is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn many_spill_files_keep_their_order() {
+    let dir = test_dir("spill-many");
+    let sink = MemorySink::default();
+    let local = spilling_local(&sink, &dir);
+    sink.fail_open("a.log", Some(io::ErrorKind::PermissionDenied));
+
+    // 追加写入和随机写入交替，无法合并，同一轮中依次放弃，各是一份溢出数据。
+    // 序号超过一位数后仍按溢出的先后顺序重新写入
+    let data = b"abcdefghijklmno";
+    for (i, byte) in data.chunks(1).enumerate() {
+        let data = match i % 2 {
+            0 => WriteData::Append(byte.to_vec().into()),
+            _ => WriteData::WriteAt {
+                offset: i as u64,
+                data: byte.to_vec().into(),
+            },
+        };
+        local.write("a.log".into(), data);
+    }
+    local.flush().unwrap();
+    assert_eq!(spill_files(&dir).len(), 2 * data.len());
+
+    sink.fail_open("a.log", None);
+    let report = local.replay_spill().unwrap();
+    assert_eq!(report.replayed, data.len());
+    assert_eq!(sink.read("a.log").unwrap(), data);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn write_at_and_non_utf8_path_round_trip() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = test_dir("spill-write-at");
+    let sink = MemorySink::default();
+    let local = spilling_local(&sink, &dir);
+    let path = PathBuf::from(OsStr::from_bytes(b"idx-\xff.bin"));
+    sink.fail_open(path.clone(), Some(io::ErrorKind::PermissionDenied));
+
+    // 合并后的随机写入每一段各溢出一份，记录各自的偏移量
+    for (offset, data) in [(5, b"xy"), (0, b"ab")] {
+        let data = data.to_vec().into();
+        local.write(path.clone(), WriteData::WriteAt { offset, data });
+    }
+    local.flush().unwrap();
+    let names = spill_files(&dir);
+    assert_eq!(names.len(), 4);
+    let offsets: Vec<_> = names
+        .iter()
+        .filter(|name| name.ends_with(".meta"))
+        .map(|name| {
+            let meta = std::fs::read(dir.join(name)).unwrap();
+            assert!(meta.ends_with(b"\npath=idx-\xff.bin"));
+            let meta = String::from_utf8_lossy(&meta).into_owned();
+            assert!(meta.starts_with("mode=write_at\n"));
+            meta.lines()
+                .find_map(|line| line.strip_prefix("offset="))
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(offsets, ["0", "5"]);
+
+    sink.fail_open(path.clone(), None);
+    let report = local.replay_spill().unwrap();
+    assert_eq!(report.replayed, 2);
+    assert_eq!(sink.read(&path).unwrap(), b"ab\0\0\0xy");
+    assert!(spill_files(&dir).is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn respilled_data_replaces_old_files() {
+    let dir = test_dir("spill-respill");
+    let sink = MemorySink::default();
+    let local = spilling_local(&sink, &dir);
+    sink.fail_open("a.log", Some(io::ErrorKind::PermissionDenied));
+    local.write("a.log".into(), WriteData::Append(b"1".to_vec().into()));
+    local.flush().unwrap();
+    let before = spill_files(&dir);
+    assert_eq!(before.len(), 2);
+
+    // 仍然写入失败时再次溢出，旧的溢出文件被删除，只留下新的一份
+    let report = local.replay_spill().unwrap();
+    assert_eq!(report.replayed, 1);
+    assert!(report.failed.is_empty());
+    let after = spill_files(&dir);
+    assert_eq!(after.len(), 2);
+    assert!(after.iter().all(|name| !before.contains(name)));
+    let data = after.iter().find(|name| name.ends_with(".data")).unwrap();
+    assert_eq!(std::fs::read(dir.join(data)).unwrap(), b"1");
+    assert_eq!(sink.read("a.log"), None);
+
+    sink.fail_open("a.log", None);
+    assert_eq!(local.replay_spill().unwrap().replayed, 1);
+    assert_eq!(sink.read("a.log").unwrap(), b"1");
+    assert!(spill_files(&dir).is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compressed_spill_is_not_compressed_again() {
+    use std::io::Read;
+    use write_local::Compression;
+
+    let dir = test_dir("spill-gzip");
+    let sink = MemorySink::default();
+    let local = spilling_local(&sink, &dir);
+    local.set_compression("a.log".into(), Compression::Gzip(6));
+    sink.fail_open("a.log", Some(io::ErrorKind::PermissionDenied));
+    local.write("a.log".into(), WriteData::Append(b"hello".to_vec().into()));
+    local.flush().unwrap();
+    let names = spill_files(&dir);
+    let meta = names.iter().find(|name| name.ends_with(".meta")).unwrap();
+    let meta = std::fs::read_to_string(dir.join(meta)).unwrap();
+    assert!(meta.contains("\ncompressed=gzip\n"));
+
+    sink.fail_open("a.log", None);
+    assert_eq!(local.replay_spill().unwrap().replayed, 1);
+    let file = sink.read("a.log").unwrap();
+    let mut decoded = Vec::new();
+    flate2::read::MultiGzDecoder::new(&file[..])
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, b"hello");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn replay_waits_for_room_regardless_of_overflow_policy() {
+    let dir = test_dir("spill-replay-full");
+    let overflow_dir = test_dir("spill-replay-overflow");
+    for policy in [
+        OverflowPolicy::DropNewest,
+        OverflowPolicy::Spill(overflow_dir.clone()),
+    ] {
+        let sink = MemorySink::default();
+        let local = spilling_local(&sink, &dir);
+        sink.fail_open("a.log", Some(io::ErrorKind::PermissionDenied));
+        for data in [b"1", b"2", b"3"] {
+            local.write("a.log".into(), WriteData::Append(data.to_vec().into()));
+            local.flush().unwrap();
+        }
+        local.shutdown().unwrap();
+        sink.fail_open("a.log", None);
+
+        // 后台写线程阻塞在`gate.log`上，channel容量为1，重新写入时channel很快就满了
+        let (entered, entered_rx) = flume::unbounded();
+        let (release, release_rx) = flume::unbounded::<()>();
+        let gate = Hooked::new(sink.clone()).on_open(move |inner, path| {
+            if path == Path::new("gate.log") {
+                let _ = entered.send(());
+                let _ = release_rx.recv();
+            }
+            inner.open(path)
+        });
+        let local = WriteLocal::builder()
+            .sink(gate)
+            .spill_dir(&dir)
+            .channel_capacity(1)
+            .max_batch_count(1)
+            .overflow_policy(policy.clone())
+            .build()
+            .unwrap();
+        local.write("gate.log".into(), WriteData::Append(b"g".to_vec().into()));
+        entered_rx.recv().unwrap();
+
+        let replaying = local.clone();
+        let replay = thread::spawn(move || replaying.replay_spill().unwrap());
+        thread::sleep(Duration::from_millis(100));
+        release.send(()).unwrap();
+        let report = replay.join().unwrap();
+        assert_eq!(report.replayed, 3, "{policy:?}");
+        assert!(report.failed.is_empty(), "{policy:?}");
+        assert_eq!(sink.read("a.log").unwrap(), b"123", "{policy:?}");
+        assert!(spill_files(&dir).is_empty(), "{policy:?}");
+        assert!(spill_files(&overflow_dir).is_empty(), "{policy:?}");
+        let overflow = local.overflow_counts();
+        assert_eq!((overflow.dropped_newest, overflow.spilled), (0, 0));
+        local.shutdown().unwrap();
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_dir_all(overflow_dir).unwrap();
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::append;
+use std::{io, path::Path, time::Duration};
+use write_local::{test_util::TestLocal, PathStats, RetryPolicy, WriteLocal};
+
+#[test]
+fn stats_count_each_path() {
+    let local = TestLocal::new();
+    append(&local, "a.log", b"1");
+    append(&local, "a.log", b"23");
+    append(&local, "b.log", b"x");
+    local.advance(Duration::from_millis(100));
+    append(&local, "a.log", b"4");
+    local.advance(Duration::from_millis(100));
+
+    let stats = local.stats();
+    assert_eq!(stats.queued, 0);
+    assert_eq!(stats.messages, 4);
+    assert_eq!(stats.merged, 1);
+    assert_eq!(stats.batches, 2);
+    assert_eq!(stats.bytes_written, 5);
+    assert_eq!(stats.write_errors, 0);
+    assert_eq!(stats.given_up, 0);
+    // 每轮每个文件写入一次
+    assert_eq!(stats.latency.count, 3);
+    assert!(stats.latency.max <= stats.latency.total);
+    assert_eq!(stats.triggers, local.trigger_counts());
+    assert_eq!(stats.paths.len(), 2);
+    let a = PathStats {
+        writes: 2,
+        bytes_written: 4,
+        errors: 0,
+    };
+    assert_eq!(stats.paths[Path::new("a.log")], a);
+    let b = PathStats {
+        writes: 1,
+        bytes_written: 1,
+        errors: 0,
+    };
+    assert_eq!(stats.paths[Path::new("b.log")], b);
+}
+
+#[test]
+fn failed_writes_are_counted_per_path() {
+    let local = TestLocal::with_builder(WriteLocal::builder().retry_policy(RetryPolicy::never()));
+    local
+        .sink()
+        .fail_open("bad.log", Some(io::ErrorKind::PermissionDenied));
+    append(&local, "bad.log", b"lost");
+    append(&local, "ok.log", b"ok");
+    local.advance(Duration::from_millis(100));
+
+    let stats = local.stats();
+    assert_eq!(stats.bytes_written, 2);
+    assert_eq!(stats.write_errors, 1);
+    assert_eq!(stats.given_up, 1);
+    assert_eq!(stats.latency.count, 2);
+    let bad = PathStats {
+        writes: 0,
+        bytes_written: 0,
+        errors: 1,
+    };
+    assert_eq!(stats.paths[Path::new("bad.log")], bad);
+    assert_eq!(stats.paths[Path::new("ok.log")].writes, 1);
+}
@@ -0,0 +1,181 @@
+#![cfg(all(feature = "io-uring", target_os = "linux"))]
+
+mod common;
+
+use common::test_dir;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+use write_local::{Durability, RetryPolicy, UringSink, WriteData, WriteError, WriteLocal};
+
+/// 文件大小限制是整个进程的，修改限制期间其他测试不能写入超过限制的数据
+static FSIZE_LIMIT: Mutex<()> = Mutex::new(());
+
+/// 使用io_uring写入，第一次失败就放弃，放弃的数据收集到返回的Vec中
+fn uring_local(sink: UringSink) -> (WriteLocal, Arc<Mutex<Vec<Vec<u8>>>>) {
+    let dead = Arc::new(Mutex::new(Vec::new()));
+    let dead_letters = dead.clone();
+    let local = WriteLocal::builder()
+        .sink(sink)
+        .durability(Durability::Fdatasync)
+        .retry_policy(RetryPolicy::never())
+        .dead_letter(move |letter| {
+            let WriteData::Append(data) = letter.data else {
+                unreachable!("only appends are written");
+            };
+            dead_letters.lock().unwrap().push(data.to_vec());
+        })
+        .build()
+        .unwrap();
+    (local, dead)
+}
+
+#[test]
+fn failed_completion_only_fails_its_file() {
+    // 内核不支持或禁止使用io_uring时跳过
+    let Ok(sink) = UringSink::new(8) else {
+        return;
+    };
+    let dir = test_dir("uring-failed");
+    let (local, dead) = uring_local(sink);
+    let ok = dir.join("ok.log");
+
+    // 写入/dev/full总是返回ENOSPC，链接在其后的fsync被取消
+    let full = local.write_with_ack("/dev/full".into(), WriteData::Append(b"x".to_vec().into()));
+    let acked = local.write_with_ack(ok.clone(), WriteData::Append(b"ok".to_vec().into()));
+    let summary = local.flush().unwrap();
+
+    assert_eq!(summary.written, vec![(ok.clone(), 2)]);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, Path::new("/dev/full"));
+    assert!(matches!(full.wait(), Err(WriteError::Io { .. })));
+    assert_eq!(acked.wait().unwrap().bytes, 2);
+    assert_eq!(std::fs::read(&ok).unwrap(), b"ok");
+    assert_eq!(*dead.lock().unwrap(), vec![b"x".to_vec()]);
+
+    local.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn partial_completion_keeps_unwritten_tail() {
+    let Ok(sink) = UringSink::new(8) else {
+        return;
+    };
+    let dir = test_dir("uring-partial");
+    let (local, dead) = uring_local(sink);
+    let path = dir.join("a.log");
+
+    // 文件大小超出限制时，writev只写入限制以内的部分，之后的写入返回EFBIG
+    let guard = FSIZE_LIMIT.lock().unwrap_or_else(PoisonError::into_inner);
+    unsafe {
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        let limit = libc::rlimit {
+            rlim_cur: 10,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+    }
+    let ack = local.write_with_ack(path.clone(), WriteData::Append(b"0123456".to_vec().into()));
+    local.write(path.clone(), WriteData::Append(b"789abcde".to_vec().into()));
+    let summary = local.flush().unwrap();
+    unsafe {
+        let limit = libc::rlimit {
+            rlim_cur: libc::RLIM_INFINITY,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        libc::setrlimit(libc::RLIMIT_FSIZE, &limit);
+    }
+    drop(guard);
+
+    // 已写入的部分不会被当作未写入，只有剩下的部分被放弃
+    assert_eq!(summary.failed.len(), 1);
+    assert!(ack.wait().is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+    assert_eq!(*dead.lock().unwrap(), vec![b"abcde".to_vec()]);
+
+    local.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn uring_sink_writes_whole_batch() {
+    // 内核不支持或禁止使用io_uring时跳过
+    let Ok(sink) = UringSink::new(4) else {
+        return;
+    };
+    let _guard = FSIZE_LIMIT.lock().unwrap_or_else(PoisonError::into_inner);
+    let dir = test_dir("uring");
+    let writer = WriteLocal::builder()
+        .sink(sink)
+        .durability(Durability::Fdatasync)
+        .build()
+        .unwrap();
+
+    // 文件数超过io_uring的容量时分多次提交
+    for round in 0..2u8 {
+        for i in 0..5 {
+            let path = dir.join(format!("{i}.log"));
+            writer.write(
+                path.clone(),
+                WriteData::Append(vec![b'a' + round; 3].into()),
+            );
+            writer.write(path, WriteData::Append(vec![b'0' + i; 5000].into()));
+        }
+        writer.flush().unwrap();
+    }
+
+    for i in 0..5u8 {
+        let mut expected = Vec::new();
+        for round in 0..2u8 {
+            expected.extend([b'a' + round; 3]);
+            expected.extend(vec![b'0' + i; 5000]);
+        }
+        assert_eq!(
+            std::fs::read(dir.join(format!("{i}.log"))).unwrap(),
+            expected
+        );
+    }
+    writer.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn uncached_files_are_opened_through_the_ring() {
+    let Ok(sink) = UringSink::new(8) else {
+        return;
+    };
+    let dir = test_dir("uring-open");
+    let (local, dead) = uring_local(sink);
+    let existing = dir.join("existing.log");
+    let created = dir.join("created.log");
+    let missing = dir.join("missing").join("a.log");
+    std::fs::write(&existing, b"old|").unwrap();
+
+    // 同一轮中打开的文件里有一个失败时，其他文件照常写入
+    for path in [&existing, &created, &missing] {
+        local.write(path.clone(), WriteData::Append(b"new".to_vec().into()));
+    }
+    let summary = local.flush().unwrap();
+
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, missing);
+    assert_eq!(*dead.lock().unwrap(), vec![b"new".to_vec()]);
+    // 以追加方式打开，不清空已有内容；新建文件的权限与std打开时相同
+    assert_eq!(std::fs::read(&existing).unwrap(), b"old|new");
+    assert_eq!(std::fs::read(&created).unwrap(), b"new");
+    let std_created = dir.join("std.log");
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&std_created)
+        .unwrap();
+    assert_eq!(
+        std::fs::metadata(&created).unwrap().permissions(),
+        std::fs::metadata(&std_created).unwrap().permissions()
+    );
+
+    local.shutdown().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
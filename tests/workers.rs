@@ -0,0 +1,76 @@
+mod common;
+
+use common::append;
+use std::{io, path::PathBuf, time::Duration};
+use write_local::{test_util::TestLocal, FlushSummary, WriteLocal};
+
+fn written(summary: &FlushSummary) -> Vec<(PathBuf, usize)> {
+    let mut written = summary.written.clone();
+    written.sort();
+    written
+}
+
+#[test]
+fn flush_and_shutdown_merge_all_workers() {
+    let local = TestLocal::with_builder(WriteLocal::builder().worker_threads(4));
+    local
+        .sink()
+        .fail_open("bad.log", Some(io::ErrorKind::StorageFull));
+    for f in 0..8 {
+        append(&local, &format!("{f}.log"), b"12");
+    }
+    append(&local, "bad.log", b"1");
+
+    // 各个后台写线程的结果合并在一起
+    let summary = local.run_batch().unwrap();
+    let expected: Vec<_> = (0..8).map(|f| (format!("{f}.log").into(), 2)).collect();
+    assert_eq!(written(&summary), expected);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, PathBuf::from("bad.log"));
+
+    // 只写入一个文件时只有负责它的线程参与
+    append(&local, "3.log", b"3");
+    append(&local, "5.log", b"5");
+    let summary = local.flush_path("3.log".as_ref()).unwrap();
+    assert_eq!(written(&summary), [("3.log".into(), 1)]);
+    assert_eq!(local.read("5.log").unwrap(), b"12");
+
+    local.sink().fail_open("bad.log", None);
+    append(&local, "0.log", b"0");
+    let summary = local.shutdown().unwrap();
+    assert_eq!(
+        written(&summary),
+        [
+            ("0.log".into(), 1),
+            ("5.log".into(), 1),
+            ("bad.log".into(), 1)
+        ]
+    );
+    assert!(summary.failed.is_empty());
+    assert_eq!(local.read("bad.log").unwrap(), b"1");
+}
+
+/// 有`workers`个后台写线程，总共最多保留`max`字节待写数据时，写入失败的6个字节是否被放弃
+fn gives_up_six_bytes(workers: usize, max: usize) -> bool {
+    let local = TestLocal::with_builder(
+        WriteLocal::builder()
+            .worker_threads(workers)
+            .max_pending_bytes(max),
+    );
+    local
+        .sink()
+        .fail_open("bad.log", Some(io::ErrorKind::StorageFull));
+    append(&local, "bad.log", b"123456");
+    local.advance(Duration::from_millis(100));
+    local.stats().given_up == 1
+}
+
+#[test]
+fn pending_budget_is_split_between_workers() {
+    assert!(!gives_up_six_bytes(1, 10));
+    // 每个线程最多保留5个字节
+    assert!(gives_up_six_bytes(2, 10));
+    // 不能平分时向上取整
+    assert!(!gives_up_six_bytes(2, 11));
+    assert!(gives_up_six_bytes(3, 11));
+}